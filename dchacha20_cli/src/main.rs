@@ -0,0 +1,267 @@
+//! `dchacha20` command line tool, encrypts and decrypts files into the
+//! container format of the `dchacha20` crate.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use dchacha20::{Algorithm, ContainerReader, ContainerWriter, Kdf, MAX_CHUNK_SIZE, NONCE_LEN, SALT_LEN};
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+/// Encrypts and decrypts files with ChaCha20 or DChaCha20.
+/// Input and output default to stdin and stdout
+#[derive(Parser)]
+#[command(name = "dchacha20", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Writes a new random key, as 64 hex digits
+    Keygen {
+        /// File to create, stdout if missing
+        #[arg(short, long)]
+        output: Option<PathBuf>
+    },
+    /// Encrypts the input into a container
+    Encrypt {
+        #[command(flatten)]
+        secret: Secret,
+        #[arg(short, long, value_enum, default_value_t = Cipher::Dchacha20)]
+        cipher: Cipher,
+        /// Key derivation of a passphrase, not applicable to a key file
+        #[arg(long, value_enum, default_value_t = KdfChoice::Argon2id, conflicts_with = "key_file")]
+        kdf: KdfChoice,
+        /// Plaintext bytes sealed together, up to 16 MiB
+        #[arg(long, default_value_t = 64 * 1024)]
+        chunk_size: u32,
+        /// File to read, stdin if missing
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// File to write, stdout if missing
+        #[arg(short, long)]
+        output: Option<PathBuf>
+    },
+    /// Decrypts a container, the cipher is read from its header
+    ///
+    /// Writing to stdout, chunks are output as they are checked: on error the
+    /// output stops short, but whatever came out is genuine
+    Decrypt {
+        #[command(flatten)]
+        secret: Secret,
+        /// File to read, stdin if missing
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// File to write, stdout if missing
+        #[arg(short, long)]
+        output: Option<PathBuf>
+    }
+}
+
+/// Where the key comes from, a key file or a passphrase
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct Secret {
+    /// File holding the key, as written by `keygen`
+    #[arg(short, long)]
+    key_file: Option<PathBuf>,
+    /// Asks for a passphrase on the terminal
+    #[arg(short, long)]
+    passphrase: bool,
+    /// File whose first line is the passphrase
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>
+}
+
+/// Key or passphrase read, cleared from memory once dropped
+enum SecretValue {
+    Key(Zeroizing<[u8; 32]>),
+    Passphrase(Zeroizing<String>)
+}
+
+/// Key derivation function turning a passphrase into a key
+#[derive(Clone, Copy, ValueEnum)]
+enum KdfChoice {
+    /// Argon2id, 64 MiB, 3 passes, 4 lanes
+    Argon2id,
+    /// scrypt, N = 2^17, r = 8, p = 1
+    Scrypt
+}
+
+impl From<KdfChoice> for Kdf {
+    fn from(kdf: KdfChoice) -> Self {
+        match kdf {
+            KdfChoice::Argon2id => Kdf::default(),
+            KdfChoice::Scrypt => Kdf::SCRYPT
+        }
+    }
+}
+
+/// Cipher of the container chunks, each sealed with its AEAD
+#[derive(Clone, Copy, ValueEnum)]
+enum Cipher {
+    /// ChaCha20-Poly1305
+    Chacha20,
+    /// DChaCha20 with its Poly1305 tag bound to the feedback digest
+    Dchacha20
+}
+
+impl From<Cipher> for Algorithm {
+    fn from(cipher: Cipher) -> Self {
+        match cipher {
+            Cipher::Chacha20 => Algorithm::ChaCha20Poly1305,
+            Cipher::Dchacha20 => Algorithm::DChaCha20Aead
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("dchacha20: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Keygen { output } => keygen(output.as_deref()),
+        Command::Encrypt { secret, cipher, kdf, chunk_size, input, output } => {
+            if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+                return Err(format!("chunk size must be between 1 and {MAX_CHUNK_SIZE}").into());
+            }
+            let secret = read_secret(&secret, true)?;
+            let mut nonce = [0u8; NONCE_LEN];
+            rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+            with_output(output.as_deref(), |output| {
+                let mut writer = match &secret {
+                    SecretValue::Key(key) => ContainerWriter::new(output, key, cipher.into(), &nonce, chunk_size)?,
+                    SecretValue::Passphrase(passphrase) => {
+                        let mut salt = [0u8; SALT_LEN];
+                        rand::rngs::OsRng.fill_bytes(&mut salt);
+                        ContainerWriter::with_password(output, passphrase.as_bytes(), kdf.into(), &salt, cipher.into(), &nonce, chunk_size)?
+                    }
+                };
+                io::copy(&mut open_input(input.as_deref())?, &mut writer)?;
+                writer.finish()?;
+                Ok(())
+            })
+        }
+        Command::Decrypt { secret, input, output } => {
+            let secret = read_secret(&secret, false)?;
+            with_output(output.as_deref(), |output| {
+                let input = open_input(input.as_deref())?;
+                let mut reader = match &secret {
+                    SecretValue::Key(key) => ContainerReader::new(input, key)?,
+                    SecretValue::Passphrase(passphrase) => ContainerReader::with_password(input, passphrase.as_bytes())?
+                };
+                io::copy(&mut reader, output)?;
+                Ok(())
+            })
+        }
+    }
+}
+
+fn keygen(output: Option<&Path>) -> Result<()> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut key = Zeroizing::new([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(key.as_mut_slice());
+    // sized up front, so that no copy of the key is left behind by a reallocation
+    let mut hex = Zeroizing::new(String::with_capacity(64));
+    for byte in key.iter() {
+        hex.push(DIGITS[(byte >> 4) as usize] as char);
+        hex.push(DIGITS[(byte & 15) as usize] as char);
+    }
+
+    match output {
+        Some(path) => {
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            writeln!(file, "{}", *hex)?;
+        }
+        None => println!("{}", *hex)
+    }
+    Ok(())
+}
+
+/// Reads the key file or passphrase `secret` points at. A passphrase typed
+/// in is asked twice when `confirm` is set
+fn read_secret(secret: &Secret, confirm: bool) -> Result<SecretValue> {
+    let passphrase = if let Some(path) = &secret.key_file {
+        return Ok(SecretValue::Key(read_key(path)?));
+    } else if let Some(path) = &secret.passphrase_file {
+        let text = Zeroizing::new(fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?);
+        Zeroizing::new(text.lines().next().unwrap_or_default().to_owned())
+    } else {
+        let passphrase = Zeroizing::new(rpassword::prompt_password("Passphrase: ")?);
+        if confirm && *Zeroizing::new(rpassword::prompt_password("Passphrase again: ")?) != *passphrase {
+            return Err("passphrases do not match".into());
+        }
+        passphrase
+    };
+
+    if passphrase.is_empty() {
+        return Err("empty passphrase".into());
+    }
+    Ok(SecretValue::Passphrase(passphrase))
+}
+
+/// Reads a key file holding 64 hex digits, surrounding whitespace aside
+fn read_key(path: &Path) -> Result<Zeroizing<[u8; 32]>> {
+    let text = Zeroizing::new(fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?);
+    let text = text.trim();
+    let invalid = || format!("{}: key must be 64 hex digits", path.display());
+    if text.len() != 64 || !text.is_ascii() {
+        return Err(invalid().into());
+    }
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    for (byte, digits) in key.iter_mut().zip(text.as_bytes().chunks(2)) {
+        let high = (digits[0] as char).to_digit(16).ok_or_else(invalid)?;
+        let low  = (digits[1] as char).to_digit(16).ok_or_else(invalid)?;
+        *byte = (high << 4 | low) as u8;
+    }
+    Ok(key)
+}
+
+fn open_input(path: Option<&Path>) -> Result<Box<dyn Read>> {
+    Ok(match path {
+        Some(path) => Box::new(BufReader::new(File::open(path).map_err(|e| format!("{}: {e}", path.display()))?)),
+        None => Box::new(io::stdin().lock())
+    })
+}
+
+/// Runs `f` on the output file, or stdout. The file is written under a
+/// temporary name next to it, flushed to disk, and only then renamed into
+/// place: a failure or a crash leaves no partial result behind, and the
+/// output may replace the input
+fn with_output(path: Option<&Path>, f: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let Some(path) = path else {
+        let mut stdout = io::stdout().lock();
+        f(&mut stdout)?;
+        return Ok(stdout.flush()?);
+    };
+
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let temp = tempfile::NamedTempFile::new_in(dir).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut output = BufWriter::new(temp.as_file());
+    f(&mut output)?;
+    output.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    temp.persist(path).map_err(|e| format!("{}: {}", path.display(), e.error))?;
+    Ok(())
+}
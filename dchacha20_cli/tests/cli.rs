@@ -0,0 +1,201 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio}
+};
+
+use tempfile::TempDir;
+
+fn dchacha20(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dchacha20"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // fails with a broken pipe if the tool exits without reading its input
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+fn path(dir: &TempDir, name: &str) -> String {
+    dir.path().join(name).to_str().unwrap().to_owned()
+}
+
+fn keygen(dir: &TempDir, name: &str) -> String {
+    let key = path(dir, name);
+    assert!(dchacha20(&["keygen", "-o", &key], b"").status.success());
+    key
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn keygen_writes_a_fresh_key() {
+    let dir = TempDir::new().unwrap();
+    let key = keygen(&dir, "key");
+    let text = fs::read_to_string(&key).unwrap();
+    assert_eq!(text.trim().len(), 64);
+    assert!(text.trim().chars().all(|c| c.is_ascii_hexdigit()));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(&key).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    // never overwrites a key
+    let output = dchacha20(&["keygen", "-o", &key], b"");
+    assert!(!output.status.success());
+    assert_eq!(fs::read_to_string(&key).unwrap(), text);
+
+    let output = dchacha20(&["keygen"], b"");
+    assert!(output.status.success());
+    assert_ne!(String::from_utf8(output.stdout).unwrap(), text);
+}
+
+#[test]
+fn files() {
+    let dir = TempDir::new().unwrap();
+    let key = keygen(&dir, "key");
+    let msg: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    fs::write(path(&dir, "plain"), &msg).unwrap();
+
+    for cipher in ["chacha20", "dchacha20"] {
+        let (sealed, opened) = (path(&dir, "sealed"), path(&dir, "opened"));
+        let output = dchacha20(&["encrypt", "-k", &key, "-c", cipher, "--chunk-size", "1000", "-i", &path(&dir, "plain"), "-o", &sealed], b"");
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_ne!(fs::read(&sealed).unwrap()[37..1037], msg[..1000]);
+
+        let output = dchacha20(&["decrypt", "-k", &key, "-i", &sealed, "-o", &opened], b"");
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(fs::read(&opened).unwrap(), msg);
+    }
+}
+
+#[test]
+fn in_place() {
+    let dir = TempDir::new().unwrap();
+    let key = keygen(&dir, "key");
+    let other = keygen(&dir, "other");
+    let file = path(&dir, "file");
+    let msg = b"hello".repeat(1000);
+    fs::write(&file, &msg).unwrap();
+
+    let output = dchacha20(&["encrypt", "-k", &key, "-i", &file, "-o", &file], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let sealed = fs::read(&file).unwrap();
+    assert_eq!(&sealed[..8], b"DCHACHA\x1a");
+
+    // a failure leaves the input as it was
+    let output = dchacha20(&["decrypt", "-k", &other, "-i", &file, "-o", &file], b"");
+    assert!(!output.status.success());
+    assert_eq!(fs::read(&file).unwrap(), sealed);
+
+    let output = dchacha20(&["decrypt", "-k", &key, "-i", &file, "-o", &file], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(fs::read(&file).unwrap(), msg);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+}
+
+#[test]
+fn pipes() {
+    let dir = TempDir::new().unwrap();
+    let key = keygen(&dir, "key");
+
+    for msg in [&b""[..], b"hello", &[9u8; 100_000]] {
+        let sealed = dchacha20(&["encrypt", "-k", &key], msg);
+        assert!(sealed.status.success(), "{}", stderr(&sealed));
+
+        let opened = dchacha20(&["decrypt", "-k", &key], &sealed.stdout);
+        assert!(opened.status.success(), "{}", stderr(&opened));
+        assert_eq!(opened.stdout, msg);
+    }
+}
+
+#[test]
+fn rejects_tampering_and_wrong_key() {
+    let dir = TempDir::new().unwrap();
+    let key = keygen(&dir, "key");
+    let other = keygen(&dir, "other");
+
+    let sealed = dchacha20(&["encrypt", "-k", &key], &[1u8; 5000]).stdout;
+    fs::write(path(&dir, "sealed"), &sealed).unwrap();
+
+    let opened = path(&dir, "opened");
+    let output = dchacha20(&["decrypt", "-k", &other, "-i", &path(&dir, "sealed"), "-o", &opened], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("authentication tag mismatch"), "{}", stderr(&output));
+    // no partial plaintext left behind
+    assert!(!Path::new(&opened).exists());
+
+    let mut tampered = sealed.clone();
+    tampered[100] ^= 1;
+    assert!(!dchacha20(&["decrypt", "-k", &key], &tampered).status.success());
+
+    let output = dchacha20(&["decrypt", "-k", &key], &sealed[..37]);
+    assert!(stderr(&output).contains("truncated container"), "{}", stderr(&output));
+}
+
+#[test]
+fn rejects_bad_arguments() {
+    let dir = TempDir::new().unwrap();
+    fs::write(path(&dir, "short"), "abcd").unwrap();
+    let output = dchacha20(&["encrypt", "-k", &path(&dir, "short")], b"hello");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("key must be 64 hex digits"));
+
+    let output = dchacha20(&["encrypt", "-k", &path(&dir, "missing")], b"hello");
+    assert!(!output.status.success());
+
+    let key = keygen(&dir, "key");
+    assert!(!dchacha20(&["encrypt", "-k", &key, "--chunk-size", "0"], b"hello").status.success());
+    assert!(!dchacha20(&["encrypt", "-k", &key, "-c", "aes"], b"hello").status.success());
+}
+
+#[test]
+fn passphrases() {
+    let dir = TempDir::new().unwrap();
+    let passphrase = path(&dir, "passphrase");
+    fs::write(&passphrase, "correct horse battery staple\nignored").unwrap();
+    let wrong = path(&dir, "wrong");
+    fs::write(&wrong, "correct horse battery stapler\n").unwrap();
+    let msg = b"hello".repeat(1000);
+
+    for kdf in ["argon2id", "scrypt"] {
+        let sealed = dchacha20(&["encrypt", "--passphrase-file", &passphrase, "--kdf", kdf], &msg);
+        assert!(sealed.status.success(), "{}", stderr(&sealed));
+
+        let opened = dchacha20(&["decrypt", "--passphrase-file", &passphrase], &sealed.stdout);
+        assert!(opened.status.success(), "{}", stderr(&opened));
+        assert_eq!(opened.stdout, msg);
+
+        let output = dchacha20(&["decrypt", "--passphrase-file", &wrong], &sealed.stdout);
+        assert!(stderr(&output).contains("authentication tag mismatch"), "{}", stderr(&output));
+    }
+
+    // a key where a passphrase is expected and the other way round
+    let key = keygen(&dir, "key");
+    let sealed = dchacha20(&["encrypt", "--passphrase-file", &passphrase], &msg).stdout;
+    let output = dchacha20(&["decrypt", "-k", &key], &sealed);
+    assert!(stderr(&output).contains("container requires a password"), "{}", stderr(&output));
+    let sealed = dchacha20(&["encrypt", "-k", &key], &msg).stdout;
+    let output = dchacha20(&["decrypt", "--passphrase-file", &passphrase], &sealed);
+    assert!(stderr(&output).contains("container requires a key"), "{}", stderr(&output));
+
+    // exactly one source
+    assert!(!dchacha20(&["encrypt"], &msg).status.success());
+    assert!(!dchacha20(&["encrypt", "-k", &key, "--passphrase-file", &passphrase], &msg).status.success());
+    // a key file is used as is, a key derivation would do nothing
+    let output = dchacha20(&["encrypt", "-k", &key, "--kdf", "scrypt"], &msg);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("cannot be used with"), "{}", stderr(&output));
+
+    fs::write(&passphrase, "\n").unwrap();
+    let output = dchacha20(&["encrypt", "--passphrase-file", &passphrase], &msg);
+    assert!(stderr(&output).contains("empty passphrase"), "{}", stderr(&output));
+}
@@ -0,0 +1,324 @@
+//! Tokio `AsyncRead`/`AsyncWrite` counterparts of the `io` adapters, enabled
+//! by the `tokio` feature.
+//!
+//! They buffer the same way, and rely on the same byte-accurate streaming of
+//! both ciphers: readers pass on whatever a ready read yields. A writer can't
+//! flush from `drop`, `poll_shutdown` is what pushes out the last bytes and
+//! ends the message.
+
+use core::{pin::Pin, task::{ready, Context, Poll}};
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use zeroize::Zeroizing;
+
+use crate::io::{apply, StreamingCipher, BUFFER_LEN};
+
+/// Encrypts what is written to it into the inner writer
+pub type AsyncEncryptWriter<C, W> = AsyncCipherWriter<C, W, true>;
+/// Decrypts what is written to it into the inner writer
+pub type AsyncDecryptWriter<C, W> = AsyncCipherWriter<C, W, false>;
+/// Reads the encryption of what the inner reader yields
+pub type AsyncEncryptReader<C, R> = AsyncCipherReader<C, R, true>;
+/// Reads the decryption of what the inner reader yields
+pub type AsyncDecryptReader<C, R> = AsyncCipherReader<C, R, false>;
+
+/// Async writer passing everything through `C` before the inner writer, see
+/// the `AsyncEncryptWriter` and `AsyncDecryptWriter` aliases.
+///
+/// `poll_flush` pushes out everything written so far, partial block included.
+/// `poll_shutdown` also ends the message before shutting the inner writer down
+pub struct AsyncCipherWriter<C: StreamingCipher, W: AsyncWrite, const ENCRYPT: bool> {
+    cipher: C,
+    inner: W,
+    /// Data waiting for the inner writer
+    buffer: Zeroizing<Vec<u8>>,
+    /// Bytes at the start of `buffer` already through the cipher
+    processed: usize,
+    /// Set once the message was ended
+    ended: bool
+}
+
+impl<C: StreamingCipher + Unpin, W: AsyncWrite + Unpin, const ENCRYPT: bool> AsyncCipherWriter<C, W, ENCRYPT> {
+    pub fn new(cipher: C, inner: W) -> Self {
+        Self {
+            cipher,
+            inner,
+            buffer: Zeroizing::new(Vec::with_capacity(BUFFER_LEN)),
+            processed: 0,
+            ended: false
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns the inner writer, dropping whatever was not flushed
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Passes the buffer through the cipher and hands it to the inner writer.
+    /// What was not written yet stays buffered, already processed
+    fn poll_flush_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        apply::<C, ENCRYPT>(&mut self.cipher, &mut self.buffer[self.processed..])?;
+        self.processed = self.buffer.len();
+
+        while !self.buffer.is_empty() {
+            match ready!(Pin::new(&mut self.inner).poll_write(cx, &self.buffer)) {
+                Ok(0) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Ok(n) => {
+                    self.buffer.drain(..n);
+                    self.processed -= n;
+                }
+                Err(e) => return Poll::Ready(Err(e))
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C: StreamingCipher + Unpin, W: AsyncWrite + Unpin, const ENCRYPT: bool> AsyncWrite for AsyncCipherWriter<C, W, ENCRYPT> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.buffer.len() == BUFFER_LEN {
+            ready!(this.poll_flush_buffer(cx))?;
+        }
+        let n = buf.len().min(BUFFER_LEN - this.buffer.len());
+        this.buffer.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buffer(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buffer(cx))?;
+        if !this.ended {
+            this.cipher.end();
+            this.ended = true;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Async reader passing what the inner reader yields through `C`, see the
+/// `AsyncEncryptReader` and `AsyncDecryptReader` aliases.
+///
+/// The message ends once the inner reader reaches EOF
+pub struct AsyncCipherReader<C: StreamingCipher, R: AsyncRead, const ENCRYPT: bool> {
+    cipher: C,
+    inner: R,
+    /// `BUFFER_LEN` bytes, processed ones from `pos` to `filled`
+    buffer: Zeroizing<Vec<u8>>,
+    pos: usize,
+    filled: usize,
+    /// Set once the message was ended
+    ended: bool
+}
+
+impl<C: StreamingCipher + Unpin, R: AsyncRead + Unpin, const ENCRYPT: bool> AsyncCipherReader<C, R, ENCRYPT> {
+    pub fn new(cipher: C, inner: R) -> Self {
+        Self {
+            cipher,
+            inner,
+            buffer: Zeroizing::new(vec![0u8; BUFFER_LEN]),
+            pos: 0,
+            filled: 0,
+            ended: false
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reads once from the inner reader, once the buffer is used up, and
+    /// passes what came through the cipher. Nothing is kept if that fails
+    fn poll_fill_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.pos    = 0;
+        self.filled = 0;
+        let mut buf = ReadBuf::new(&mut self.buffer);
+        ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+        let n = buf.filled().len();
+
+        apply::<C, ENCRYPT>(&mut self.cipher, &mut self.buffer[..n])?;
+        self.filled = n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C: StreamingCipher + Unpin, R: AsyncRead + Unpin, const ENCRYPT: bool> AsyncRead for AsyncCipherReader<C, R, ENCRYPT> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        if this.pos == this.filled {
+            ready!(this.poll_fill_buffer(cx))?;
+            if this.filled == 0 {
+                if !this.ended {
+                    this.cipher.end();
+                    this.ended = true;
+                }
+                return Poll::Ready(Ok(()));
+            }
+        }
+
+        let n = buf.remaining().min(this.filled - this.pos);
+        buf.put_slice(&this.buffer[this.pos..this.pos + n]);
+        this.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use core::{future::poll_fn, pin::Pin, task::Poll};
+
+    use rand::{Rng, RngCore};
+    use tokio::io::{duplex, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+    use crate::{io::StreamingCipher, ChaCha20, DChaCha20};
+    use super::{AsyncDecryptReader, AsyncDecryptWriter, AsyncEncryptReader, AsyncEncryptWriter};
+
+    /// Writes `data` in random sized pieces, flushing now and then, then
+    /// shuts the writer down
+    async fn write_randomly(mut writer: impl AsyncWrite + Unpin, data: Vec<u8>) {
+        let mut data = data.as_slice();
+        while !data.is_empty() {
+            let (n, flush) = {
+                let mut rand = rand::thread_rng();
+                (rand.gen_range(0..=data.len().min(10_000)), rand.gen_bool(0.1))
+            };
+            writer.write_all(&data[..n]).await.unwrap();
+            if flush {
+                writer.flush().await.unwrap();
+            }
+            data = &data[n..];
+        }
+        writer.shutdown().await.unwrap();
+    }
+
+    /// Reads everything in random sized pieces
+    async fn read_randomly(mut reader: impl AsyncRead + Unpin) -> Vec<u8> {
+        let mut data = Vec::new();
+        loop {
+            let mut buf = vec![0u8; rand::thread_rng().gen_range(1..10_000)];
+            match reader.read(&mut buf).await.unwrap() {
+                0 => return data,
+                n => data.extend_from_slice(&buf[..n])
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn chacha20() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let mut msg = vec![0u8; 100_000];
+        rand.fill_bytes(&mut msg);
+        let mut expected = msg.clone();
+        ChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        // writer on one end of the pipe, reader on the other
+        let (client, server) = duplex(1000);
+        let writer = write_randomly(AsyncEncryptWriter::new(ChaCha20::new(&key, &nonce), client), msg.clone());
+        let (_, ciphertext) = tokio::join!(writer, read_randomly(server));
+        assert_eq!(ciphertext, expected);
+
+        let (client, server) = duplex(1000);
+        let writer = write_randomly(client, expected.clone());
+        let (_, plaintext) = tokio::join!(writer, read_randomly(AsyncDecryptReader::new(ChaCha20::new(&key, &nonce), server)));
+        assert_eq!(plaintext, msg);
+
+        let (client, server) = duplex(1000);
+        let writer = write_randomly(client, msg.clone());
+        let (_, ciphertext) = tokio::join!(writer, read_randomly(AsyncEncryptReader::new(ChaCha20::new(&key, &nonce), server)));
+        assert_eq!(ciphertext, expected);
+
+        let (client, server) = duplex(1000);
+        let writer = write_randomly(AsyncDecryptWriter::new(ChaCha20::new(&key, &nonce), client), expected.clone());
+        let (_, plaintext) = tokio::join!(writer, read_randomly(server));
+        assert_eq!(plaintext, msg);
+    }
+
+    #[tokio::test]
+    async fn dchacha20() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        for len in [0, 1, 63, 64, 65, 100_000, 100_001] {
+            let mut msg = vec![0u8; len];
+            rand.fill_bytes(&mut msg);
+            let mut expected = msg.clone();
+            let mut expected2 = b"next message".to_vec();
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            cipher.encrypt(&mut expected);
+            cipher.encrypt(&mut expected2);
+
+            let (client, server) = duplex(1000);
+            let writer = write_randomly(AsyncEncryptWriter::new(DChaCha20::new(&key, &nonce), client), msg.clone());
+            let (_, ciphertext) = tokio::join!(writer, read_randomly(server));
+            assert_eq!(ciphertext, expected);
+
+            let (client, server) = duplex(1000);
+            let writer = write_randomly(client, msg.clone());
+            let (_, ciphertext) = tokio::join!(writer, read_randomly(AsyncEncryptReader::new(DChaCha20::new(&key, &nonce), server)));
+            assert_eq!(ciphertext, expected);
+
+            let (client, server) = duplex(1000);
+            let writer = write_randomly(AsyncDecryptWriter::new(DChaCha20::new(&key, &nonce), client), expected.clone());
+            let (_, plaintext) = tokio::join!(writer, read_randomly(server));
+            assert_eq!(plaintext, msg);
+
+            // the final partial block is folded into the digest, the next
+            // message starts where an uninterrupted cipher would
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            let (client, server) = duplex(1000);
+            let writer = write_randomly(client, expected.clone());
+            let (_, plaintext) = tokio::join!(writer, read_randomly(AsyncDecryptReader::new(&mut cipher, server)));
+            assert_eq!(plaintext, msg);
+            cipher.decrypt(&mut expected2);
+            assert_eq!(expected2, b"next message");
+        }
+    }
+
+    /// Sends messages one flush at a time over a live pipe, each must be
+    /// readable right away, before the next one is sent
+    async fn exchange<C: StreamingCipher + Unpin>(new: impl Fn() -> C) {
+        let msgs: [&[u8]; 3] = [b"ping", b"pong, longer than a block so that it crosses into the next one", b"bye"];
+        let (client, server) = duplex(1000);
+        let mut writer = AsyncEncryptWriter::new(new(), client);
+        let mut reader = AsyncDecryptReader::new(new(), server);
+        let mut buf = [0u8; 100];
+        for msg in msgs {
+            writer.write_all(msg).await.unwrap();
+            writer.flush().await.unwrap();
+
+            let mut read = ReadBuf::new(&mut buf);
+            let polled = poll_fn(|cx| Poll::Ready(Pin::new(&mut reader).poll_read(cx, &mut read))).await;
+            assert!(matches!(polled, Poll::Ready(Ok(()))));
+            assert_eq!(read.filled(), msg);
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_blocks() {
+        exchange(|| ChaCha20::new(&[1u8; 32], &[2u8; 12])).await;
+        exchange(|| DChaCha20::new(&[1u8; 32], &[2u8; 12])).await;
+    }
+}
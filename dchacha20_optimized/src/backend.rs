@@ -0,0 +1,285 @@
+//! Runtime selection of the code generating the keystream of large buffers.
+//!
+//! The `simd` types are fixed when the crate is built, so a generic x86_64
+//! build never uses AVX2. The ciphers instead pick a `Backend` when they are
+//! created, from what the CPU they run on supports, and generate several
+//! blocks at once with vectors of that instruction set. Single blocks and
+//! partial blocks always go through the portable code.
+
+use crate::{chacha20::ChaCha, simd::{u32x4, u32x8, Lanes}};
+
+/// Instruction set the keystream of large buffers is generated with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// The `simd` types of the build, 4 blocks at a time, or 8 when the
+    /// build itself enables AVX2. Available everywhere
+    Portable,
+    /// 4 blocks at a time in 128 bit registers, x86_64 only
+    Sse2,
+    /// 8 blocks at a time in 256 bit registers, x86_64 only
+    Avx2,
+    /// 16 blocks at a time in 512 bit registers, x86_64 with AVX-512F only
+    Avx512,
+    /// 4 blocks at a time in 128 bit registers, aarch64 only
+    Neon
+}
+
+/// Whether the CPU supports an x86 feature. Without `std` there is no
+/// runtime detection, only what the build enables is known to be there
+#[cfg(target_arch = "x86_64")]
+macro_rules! x86_feature {
+    ($feature:tt) => {{
+        #[cfg(feature = "std")]
+        let supported = std::arch::is_x86_feature_detected!($feature);
+        #[cfg(not(feature = "std"))]
+        let supported = cfg!(target_feature = $feature);
+        supported
+    }};
+}
+
+impl Backend {
+    /// Every backend, fastest first
+    pub const ALL: [Backend; 5] = [Backend::Avx512, Backend::Avx2, Backend::Sse2, Backend::Neon, Backend::Portable];
+
+    /// Fastest backend the CPU supports
+    pub fn detect() -> Self {
+        Self::ALL.into_iter().find(|backend| backend.is_supported()).unwrap_or(Backend::Portable)
+    }
+
+    /// Whether the CPU the program runs on can use this backend
+    pub fn is_supported(self) -> bool {
+        match self {
+            Backend::Portable => true,
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse2 => true,
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => x86_feature!("avx2"),
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => x86_feature!("avx512f"),
+            #[cfg(target_arch = "aarch64")]
+            Backend::Neon => cfg!(target_feature = "neon"),
+            #[allow(unreachable_patterns)]
+            _ => false
+        }
+    }
+
+    /// XORs the keystream of as many blocks as the backend handles at once
+    /// into `buff`, starting at the counter in `state`, and returns how many
+    /// blocks it went through. What is left is shorter than a group of blocks
+    #[inline]
+    pub(crate) fn apply_keystream<const R: usize, const LEGACY: bool>(self, mut state: [u32; 16], buff: &mut [u8]) -> usize {
+        debug_assert!(self.is_supported());
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Backend::Sse2 => wide::<R, LEGACY, x86::Sse2, 4>(&mut state, buff),
+            // SAFETY: the ciphers only use backends `is_supported` accepts
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx2 => unsafe { x86::avx2::<R, LEGACY>(&mut state, buff) },
+            #[cfg(target_arch = "x86_64")]
+            Backend::Avx512 => unsafe { x86::avx512::<R, LEGACY>(&mut state, buff) },
+            #[cfg(target_arch = "aarch64")]
+            Backend::Neon => wide::<R, LEGACY, neon::Neon, 4>(&mut state, buff),
+            _ => portable::<R, LEGACY>(&mut state, buff)
+        }
+    }
+}
+
+
+/// Runs the wide keystream over every whole group of `N` blocks of `buff`
+#[inline(always)]
+fn wide<const R: usize, const LEGACY: bool, V, const N: usize>(state: &mut [u32; 16], buff: &mut [u8]) -> usize
+where
+    V: Lanes + From<[u32; N]> + Into<[u32; N]>
+{
+    let mut chunks = buff.chunks_exact_mut(N * 64);
+    let mut blocks = 0;
+    for chunk in &mut chunks {
+        ChaCha::<R, LEGACY>::apply_keystream_wide::<V, N>(state, chunk);
+        blocks += N;
+    }
+    blocks
+}
+
+/// With 128 bit vectors the 8 lanes take two registers each and the state
+/// no longer fits, so 8 blocks at a time only pays off when the build enables AVX2
+#[inline(always)]
+fn portable<const R: usize, const LEGACY: bool>(state: &mut [u32; 16], buff: &mut [u8]) -> usize {
+    let mut blocks = 0;
+    if cfg!(target_feature = "avx2") {
+        blocks = wide::<R, LEGACY, u32x8, 8>(state, buff);
+    }
+    blocks + wide::<R, LEGACY, u32x4, 4>(state, &mut buff[blocks * 64..])
+}
+
+
+/// Lane types over the x86 intrinsics, only ever built inside the functions
+/// enabling the matching target feature
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use core::{
+        arch::x86_64::*,
+        ops::{AddAssign, BitOr, BitXorAssign, Shl, Shr}
+    };
+
+    use super::wide;
+
+    macro_rules! lanes {
+        ($name:ident, $n:literal, $vec:ty, $load:ident, $store:ident, $add:ident, $xor:ident, $or:ident, $sll:ident, $srl:ident) => {
+            #[derive(Clone, Copy)]
+            pub(super) struct $name($vec);
+
+            // SAFETY (all of the impls): the values only exist on CPUs with
+            // the feature, see `Backend::apply_keystream`
+            impl From<[u32; $n]> for $name {
+                #[inline(always)]
+                fn from(array: [u32; $n]) -> Self {
+                    Self(unsafe { $load(array.as_ptr().cast()) })
+                }
+            }
+
+            impl From<$name> for [u32; $n] {
+                #[inline(always)]
+                fn from(lanes: $name) -> Self {
+                    let mut array = [0u32; $n];
+                    unsafe { $store(array.as_mut_ptr().cast(), lanes.0) };
+                    array
+                }
+            }
+
+            impl AddAssign for $name {
+                #[inline(always)]
+                fn add_assign(&mut self, rhs: Self) {
+                    self.0 = unsafe { $add(self.0, rhs.0) };
+                }
+            }
+
+            impl BitXorAssign for $name {
+                #[inline(always)]
+                fn bitxor_assign(&mut self, rhs: Self) {
+                    self.0 = unsafe { $xor(self.0, rhs.0) };
+                }
+            }
+
+            impl BitOr for $name {
+                type Output = Self;
+
+                #[inline(always)]
+                fn bitor(self, rhs: Self) -> Self {
+                    Self(unsafe { $or(self.0, rhs.0) })
+                }
+            }
+
+            impl Shl<u32> for $name {
+                type Output = Self;
+
+                #[inline(always)]
+                fn shl(self, rhs: u32) -> Self {
+                    Self(unsafe { $sll(self.0, _mm_cvtsi32_si128(rhs as i32)) })
+                }
+            }
+
+            impl Shr<u32> for $name {
+                type Output = Self;
+
+                #[inline(always)]
+                fn shr(self, rhs: u32) -> Self {
+                    Self(unsafe { $srl(self.0, _mm_cvtsi32_si128(rhs as i32)) })
+                }
+            }
+        };
+    }
+
+    lanes!(Sse2, 4, __m128i, _mm_loadu_si128, _mm_storeu_si128, _mm_add_epi32, _mm_xor_si128, _mm_or_si128, _mm_sll_epi32, _mm_srl_epi32);
+    lanes!(Avx2, 8, __m256i, _mm256_loadu_si256, _mm256_storeu_si256, _mm256_add_epi32, _mm256_xor_si256, _mm256_or_si256, _mm256_sll_epi32, _mm256_srl_epi32);
+    lanes!(Avx512, 16, __m512i, _mm512_loadu_si512, _mm512_storeu_si512, _mm512_add_epi32, _mm512_xor_si512, _mm512_or_si512, _mm512_sll_epi32, _mm512_srl_epi32);
+
+    /// # Safety
+    /// The CPU must support AVX2
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn avx2<const R: usize, const LEGACY: bool>(state: &mut [u32; 16], buff: &mut [u8]) -> usize {
+        let blocks = wide::<R, LEGACY, Avx2, 8>(state, buff);
+        blocks + wide::<R, LEGACY, Sse2, 4>(state, &mut buff[blocks * 64..])
+    }
+
+    /// # Safety
+    /// The CPU must support AVX-512F, which comes with AVX2
+    #[target_feature(enable = "avx512f,avx2")]
+    pub(super) unsafe fn avx512<const R: usize, const LEGACY: bool>(state: &mut [u32; 16], buff: &mut [u8]) -> usize {
+        let mut blocks = wide::<R, LEGACY, Avx512, 16>(state, buff);
+        blocks += wide::<R, LEGACY, Avx2, 8>(state, &mut buff[blocks * 64..]);
+        blocks + wide::<R, LEGACY, Sse2, 4>(state, &mut buff[blocks * 64..])
+    }
+}
+
+
+/// NEON lanes, part of the aarch64 baseline
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use core::{
+        arch::aarch64::*,
+        ops::{AddAssign, BitOr, BitXorAssign, Shl, Shr}
+    };
+
+    #[derive(Clone, Copy)]
+    pub(super) struct Neon(uint32x4_t);
+
+    // SAFETY (all of the impls): NEON is checked by `Backend::is_supported`
+    // and the loads and stores cover exactly the 4 lanes of the array
+    impl From<[u32; 4]> for Neon {
+        #[inline(always)]
+        fn from(array: [u32; 4]) -> Self {
+            Self(unsafe { vld1q_u32(array.as_ptr()) })
+        }
+    }
+
+    impl From<Neon> for [u32; 4] {
+        #[inline(always)]
+        fn from(lanes: Neon) -> Self {
+            let mut array = [0u32; 4];
+            unsafe { vst1q_u32(array.as_mut_ptr(), lanes.0) };
+            array
+        }
+    }
+
+    impl AddAssign for Neon {
+        #[inline(always)]
+        fn add_assign(&mut self, rhs: Self) {
+            self.0 = unsafe { vaddq_u32(self.0, rhs.0) };
+        }
+    }
+
+    impl BitXorAssign for Neon {
+        #[inline(always)]
+        fn bitxor_assign(&mut self, rhs: Self) {
+            self.0 = unsafe { veorq_u32(self.0, rhs.0) };
+        }
+    }
+
+    impl BitOr for Neon {
+        type Output = Self;
+
+        #[inline(always)]
+        fn bitor(self, rhs: Self) -> Self {
+            Self(unsafe { vorrq_u32(self.0, rhs.0) })
+        }
+    }
+
+    impl Shl<u32> for Neon {
+        type Output = Self;
+
+        #[inline(always)]
+        fn shl(self, rhs: u32) -> Self {
+            Self(unsafe { vshlq_u32(self.0, vdupq_n_s32(rhs as i32)) })
+        }
+    }
+
+    impl Shr<u32> for Neon {
+        type Output = Self;
+
+        /// `vshlq_u32` shifts right for negative counts
+        #[inline(always)]
+        fn shr(self, rhs: u32) -> Self {
+            Self(unsafe { vshlq_u32(self.0, vdupq_n_s32(-(rhs as i32))) })
+        }
+    }
+}
@@ -1,14 +1,75 @@
-use std::{ops::AddAssign, simd::{u32x16, u32x4, u8x64, ToBytes}};
+use core::{mem::MaybeUninit, ops::AddAssign};
 
-pub struct ChaCha20 {
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::{backend::Backend, error::Error, simd::{u32x16, u32x4, u8x64, Lanes, ToBytes}, state::{CipherState, Kind, Snapshot}};
+
+/// ChaCha reduced to 8 rounds
+pub type ChaCha8 = ChaCha<8>;
+/// ChaCha reduced to 12 rounds
+pub type ChaCha12 = ChaCha<12>;
+/// ChaCha20 as specified in RFC 8439
+pub type ChaCha20 = ChaCha<20>;
+/// Original ChaCha20 layout with a 64 bit counter and a 64 bit nonce
+pub type ChaCha20Legacy = ChaCha<20, true>;
+
+/// Length of the keystream for a single nonce, 2^32 blocks of 64 bytes
+const KEYSTREAM_LEN: u64 = 64 << 32;
+
+/// ChaCha stream cipher with `R` rounds, see the `ChaCha8`, `ChaCha12` and
+/// `ChaCha20` aliases.
+/// With `LEGACY` the state follows the original layout, where the block
+/// counter carries from word 12 into word 13 and the nonce is 64 bit
+pub struct ChaCha<const R: usize, const LEGACY: bool = false> {
     /// This is where the initial state is stored
     state: u32x16,
     /// Calculated keystream
-    keystream: u32x16
+    keystream: u32x16,
+    /// Keystream of the last block as u8 buffer, its unused part is
+    /// consumed by the next call
+    keystream_buffer: u8x64,
+    /// Bytes of `keystream_buffer` already used, 0 when there are none left
+    offset: usize,
+    /// Set once the block counter went past its last value
+    exhausted: bool,
+    /// Code generating the keystream of large buffers
+    backend: Backend
 }
 
-impl ChaCha20 {
+impl<const R: usize> ChaCha<R> {
     pub fn new(key: &[u8; 32], nonce: &[u8; 12]) -> Self {
+        Self::new_with_counter(key, nonce, 0)
+    }
+
+    /// Same as `new` but the keystream starts at block `counter`.
+    /// RFC 8439 encryption starts at 1, block 0 being reserved for the Poly1305 key
+    pub fn new_with_counter(key: &[u8; 32], nonce: &[u8; 12], counter: u32) -> Self {
+        Self::with_state(key, [
+            counter, u32_from_le_bytes(&nonce[..4]), u32_from_le_bytes(&nonce[4..8]), u32_from_le_bytes(&nonce[8..12]),
+        ])
+    }
+}
+
+impl<const R: usize> ChaCha<R, true> {
+    pub fn new(key: &[u8; 32], nonce: &[u8; 8]) -> Self {
+        Self::new_with_counter(key, nonce, 0)
+    }
+
+    /// Same as `new` but the keystream starts at block `counter`
+    pub fn new_with_counter(key: &[u8; 32], nonce: &[u8; 8], counter: u64) -> Self {
+        Self::with_state(key, [
+            counter as u32, (counter >> 32) as u32, u32_from_le_bytes(&nonce[..4]), u32_from_le_bytes(&nonce[4..8]),
+        ])
+    }
+}
+
+impl<const R: usize, const LEGACY: bool> ChaCha<R, LEGACY> {
+    /// Number of blocks the counter can address
+    pub(crate) const BLOCKS: u128 = if LEGACY { 1 << 64 } else { 1 << 32 };
+
+    /// `last_row` holds the counter and nonce words, laid out as the variant expects
+    fn with_state(key: &[u8; 32], last_row: [u32; 4]) -> Self {
+        const { assert!(R > 0 && R.is_multiple_of(2), "ChaCha round count must be even") };
         Self {
             state: u32x16::from_array([
                 /*
@@ -17,6 +78,7 @@ impl ChaCha20 {
                 kkkkkkkk kkkkkkkk kkkkkkkk kkkkkkkk
                 bbbbbbbb nnnnnnnn nnnnnnnn nnnnnnnn
                 c=constant k=key b=blockcount n=nonce
+                (legacy: bbbbbbbb bbbbbbbb nnnnnnnn nnnnnnnn)
                 */
 
                 // The 4 u32 constants of chacha
@@ -25,14 +87,114 @@ impl ChaCha20 {
                 u32_from_le_bytes(&key[0..4]), u32_from_le_bytes(&key[4..8]), u32_from_le_bytes(&key[8..12]), u32_from_le_bytes(&key[12..16]),
                 u32_from_le_bytes(&key[16..20]), u32_from_le_bytes(&key[20..24]), u32_from_le_bytes(&key[24..28]), u32_from_le_bytes(&key[28..32]),
                 // Bit counter + nonce
-                0, u32_from_le_bytes(&nonce[..4]), u32_from_le_bytes(&nonce[4..8]), u32_from_le_bytes(&nonce[8..12]),
+                last_row[0], last_row[1], last_row[2], last_row[3],
             ]),
-            keystream: u32x16::from_array([0u32; 16])
+            keystream: u32x16::from_array([0u32; 16]),
+            keystream_buffer: u8x64::from_array([0u8; 64]),
+            offset: 0,
+            exhausted: false,
+            backend: Backend::detect()
         }
     }
 
+    /// Backend picked for this CPU when the cipher was created
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Forces the keystream of large buffers through `backend`, mostly to
+    /// test one that is not the fastest
+    ///
+    /// # Panics
+    /// If the CPU does not support `backend`
+    pub fn set_backend(&mut self, backend: Backend) {
+        assert!(backend.is_supported(), "{backend:?} backend not supported by this CPU");
+        self.backend = backend;
+    }
+
+    /// Moves the keystream to `byte_offset`, so that the next call to
+    /// `encrypt`/`decrypt` processes data starting at that position
+    ///
+    /// # Panics
+    /// If `byte_offset` is beyond the 2^32 blocks the 32 bit IETF counter can address
+    pub fn seek(&mut self, byte_offset: u64) {
+        assert!(LEGACY || byte_offset <= KEYSTREAM_LEN, "seek offset beyond the ChaCha20 keystream");
+        self.set_position(byte_offset as u128);
+    }
+
+    /// Same as `seek` for any position up to the end of the keystream,
+    /// which the 64 bit counter puts past `u64::MAX`
+    pub(crate) fn set_position(&mut self, position: u128) {
+        debug_assert!(position <= Self::BLOCKS * 64);
+        let block = position / 64;
+        self.state[12] = block as u32;
+        if LEGACY {
+            self.state[13] = (block >> 32) as u32;
+        }
+        self.exhausted = position == Self::BLOCKS * 64;
+        self.offset    = (position % 64) as usize;
+        if self.offset > 0 {
+            self.block_fn();
+            self.keystream_buffer = self.keystream.to_le_bytes();
+        }
+    }
+
+    /// Byte position in the keystream, which goes past `u64::MAX` with the
+    /// 64 bit counter
+    pub(crate) fn position(&self) -> u128 {
+        let next_block = if self.exhausted {
+            Self::BLOCKS
+        } else if LEGACY {
+            (self.state[13] as u128) << 32 | self.state[12] as u128
+        } else {
+            self.state[12] as u128
+        };
+        match self.offset {
+            0 => next_block * 64,
+            // the buffered block is the one before the counter
+            n => (next_block - 1) * 64 + n as u128
+        }
+    }
+
+    /// Byte position in the keystream the next call to `encrypt`/`decrypt` starts at.
+    /// Saturates at `u64::MAX` for legacy counters past 2^58 blocks
+    pub fn current_pos(&self) -> u64 {
+        u64::try_from(self.position()).unwrap_or(u64::MAX)
+    }
+
+    /// Bytes of keystream left before the block counter runs out,
+    /// saturating at `u64::MAX`
+    pub fn remaining(&self) -> u64 {
+        u64::try_from(Self::BLOCKS * 64 - self.position()).unwrap_or(u64::MAX)
+    }
+
+    /// Snapshot of the cipher, to resume it with `from_state` after a restart
+    pub fn export_state(&self) -> CipherState {
+        let snapshot = Zeroizing::new(Snapshot {
+            state: self.state.to_array(),
+            offset: self.offset,
+            exhausted: self.exhausted,
+            ..Default::default()
+        });
+        CipherState::new(Kind::ChaCha, R, LEGACY, &snapshot)
+    }
+
+    /// Cipher at the keystream position `state` was exported at.
+    /// Returns `Error::InvalidState` if `state` comes from a DChaCha or a
+    /// ChaCha with another round count or counter layout
+    pub fn from_state(state: &CipherState) -> Result<Self, Error> {
+        let snapshot = state.snapshot(Kind::ChaCha, R, LEGACY)?;
+        let mut cipher = Self::with_state(&[0u8; 32], [0; 4]);
+        cipher.state     = u32x16::from_array(snapshot.state);
+        cipher.exhausted = snapshot.exhausted;
+        cipher.offset    = snapshot.offset;
+        // regenerates the keystream of a block partly used
+        cipher.set_position(cipher.position());
+        Ok(cipher)
+    }
+
     #[inline(always)]
-    fn quarter_round(a: &mut u32x4, b: &mut u32x4, c: &mut u32x4, d: &mut u32x4) {
+    fn quarter_round<V: Lanes>(a: &mut V, b: &mut V, c: &mut V, d: &mut V) {
         a.add_assign(*b);
         *d ^= *a;
         *d  = (*d << 16) | (*d >> 16);
@@ -48,12 +210,12 @@ impl ChaCha20 {
     }
 
     #[inline(always)]
-    fn rounds(state: &mut u32x16) {
+    pub(crate) fn rounds(state: &mut u32x16) {
         let mut a = u32x4::from_slice(&state[0..4]);
         let mut b = u32x4::from_slice(&state[4..8]);
         let mut c = u32x4::from_slice(&state[8..12]);
         let mut d = u32x4::from_slice(&state[12..16]);
-        for _ in 0..10 {
+        for _ in 0..R / 2 {
             // column round
             Self::quarter_round(&mut a, &mut b, &mut c, &mut d);
 
@@ -75,44 +237,306 @@ impl ChaCha20 {
         d.copy_to_slice(&mut state[12..16]);
     }
 
+    /// XORs the keystream of the `N` blocks starting at the counter in `state`
+    /// into `buff`, which is `N * 64` bytes long, and moves the counter past them.
+    /// Lane `n` of `x[i]` holds word `i` of block `n`, so the rounds work on
+    /// all the blocks at once without shuffling lanes around
+    #[inline(always)]
+    pub(crate) fn apply_keystream_wide<V: Lanes + From<[u32; N]> + Into<[u32; N]>, const N: usize>(state: &mut [u32; 16], buff: &mut [u8]) {
+        let mut low  = [0u32; N];
+        let mut high = [state[13]; N];
+        for (n, (low, high)) in low.iter_mut().zip(&mut high).enumerate() {
+            let (counter, carry) = state[12].overflowing_add(n as u32);
+            *low = counter;
+            if LEGACY && carry {
+                *high = high.wrapping_add(1);
+            }
+        }
+
+        let initial: [V; 16] = core::array::from_fn(|i| match i {
+            12 => V::from(low),
+            13 => V::from(high),
+            _  => V::from([state[i]; N])
+        });
+        let mut x = initial;
+        for _ in 0..R / 2 {
+            let [x0, x1, x2, x3, x4, x5, x6, x7, x8, x9, x10, x11, x12, x13, x14, x15] = &mut x;
+            // column round
+            Self::quarter_round(x0, x4, x8,  x12);
+            Self::quarter_round(x1, x5, x9,  x13);
+            Self::quarter_round(x2, x6, x10, x14);
+            Self::quarter_round(x3, x7, x11, x15);
+            // diagonal round
+            Self::quarter_round(x0, x5, x10, x15);
+            Self::quarter_round(x1, x6, x11, x12);
+            Self::quarter_round(x2, x7, x8,  x13);
+            Self::quarter_round(x3, x4, x9,  x14);
+        }
+        for (word, init) in x.iter_mut().zip(initial) {
+            *word += init;
+        }
+
+        let words: [[u32; N]; 16] = x.map(Into::into);
+        for (n, block) in buff.chunks_exact_mut(64).enumerate() {
+            let keystream = u32x16::from_array(core::array::from_fn(|i| words[i][n]));
+            let mut b = u8x64::from_slice(block);
+            b ^= keystream.to_le_bytes();
+            b.copy_to_slice(block);
+        }
+
+        let (counter, carry) = state[12].overflowing_add(N as u32);
+        state[12] = counter;
+        if LEGACY && carry {
+            state[13] = state[13].wrapping_add(1);
+        }
+    }
+
     #[inline(always)]
     fn block_fn(&mut self) {
         self.keystream = self.state;
         Self::rounds(&mut self.keystream);
         self.keystream.add_assign(&self.state);
-        self.state[12] = self.state[12].wrapping_add(1);
+        self.increment_counter();
+    }
+
+    #[inline(always)]
+    fn increment_counter(&mut self) {
+        let (counter, overflow) = self.state[12].overflowing_add(1);
+        self.state[12] = counter;
+        if overflow {
+            if LEGACY {
+                let (high, overflow) = self.state[13].overflowing_add(1);
+                self.state[13] = high;
+                self.exhausted |= overflow;
+            } else {
+                self.exhausted = true;
+            }
+        }
     }
 
     #[inline(always)]
     fn apply_keystream(&mut self, buff: &mut [u8]) {
         self.block_fn();
 
-        if buff.len() == 64 {
-            let mut b = u8x64::from_slice(buff);
-            b ^= self.keystream.to_le_bytes();
-            b.copy_to_slice(buff);
-        } else {
-            let ks = self.keystream.to_le_bytes();
-            for (i, byte) in buff.iter_mut().enumerate() {
-                *byte ^= ks[i];
+        let mut b = u8x64::from_slice(buff);
+        b ^= self.keystream.to_le_bytes();
+        b.copy_to_slice(buff);
+    }
+
+    fn process(&mut self, mut buff: &mut [u8]) {
+        // use up the keystream left over by the previous call or by `seek`
+        if self.offset > 0 && !buff.is_empty() {
+            let (head, rest) = buff.split_at_mut((64 - self.offset).min(buff.len()));
+            for (byte, k) in head.iter_mut().zip(&self.keystream_buffer[self.offset..]) {
+                *byte ^= k;
             }
+            self.offset = (self.offset + head.len()) % 64;
+            buff = rest;
         }
-    }
 
-    pub fn encrypt(&mut self, plaintext: &mut [u8]) {
-        for chunk in plaintext.chunks_mut(64) {
+        // several blocks at a time while there is enough data, one at a time
+        // for what is left
+        let blocks = self.backend.apply_keystream::<R, LEGACY>(self.state.to_array(), buff);
+        for _ in 0..blocks {
+            self.increment_counter();
+        }
+
+        let mut chunks = buff[blocks * 64..].chunks_exact_mut(64);
+        for chunk in &mut chunks {
             self.apply_keystream(chunk);
         }
+
+        // keep the rest of the block around for the next call
+        let tail = chunks.into_remainder();
+        if !tail.is_empty() {
+            self.block_fn();
+            self.keystream_buffer = self.keystream.to_le_bytes();
+            for (byte, k) in tail.iter_mut().zip(self.keystream_buffer.as_array()) {
+                *byte ^= k;
+            }
+            self.offset = tail.len();
+        }
+    }
+
+    /// Encrypts `plaintext` in place, or returns `Error::LimitExceeded`
+    /// without touching it if the keystream left is too short
+    pub fn try_encrypt(&mut self, plaintext: &mut [u8]) -> Result<(), Error> {
+        if plaintext.len() as u64 > self.remaining() {
+            return Err(Error::LimitExceeded);
+        }
+        self.process(plaintext);
+        Ok(())
     }
 
+    /// Decrypts `ciphertext` in place, or returns `Error::LimitExceeded`
+    /// without touching it if the keystream left is too short
+    pub fn try_decrypt(&mut self, ciphertext: &mut [u8]) -> Result<(), Error> {
+        if ciphertext.len() as u64 > self.remaining() {
+            return Err(Error::LimitExceeded);
+        }
+        self.process(ciphertext);
+        Ok(())
+    }
+
+    /// # Panics
+    /// If the keystream runs out, see `try_encrypt`
+    pub fn encrypt(&mut self, plaintext: &mut [u8]) {
+        self.try_encrypt(plaintext).expect("ChaCha20 keystream exhausted");
+    }
+
+    /// # Panics
+    /// If the keystream runs out, see `try_decrypt`
     pub fn decrypt(&mut self, ciphertext: &mut [u8]) {
-        for chunk in ciphertext.chunks_mut(64) {
-            self.apply_keystream(chunk);
+        self.try_decrypt(ciphertext).expect("ChaCha20 keystream exhausted");
+    }
+
+    /// Encrypts `plaintext` into `ciphertext`, leaving `plaintext` as is.
+    /// Returns `Error::InvalidLength` if the buffers differ in length, or
+    /// `Error::LimitExceeded` if the keystream left is too short,
+    /// `ciphertext` is left untouched on error
+    pub fn encrypt_b2b(&mut self, plaintext: &[u8], ciphertext: &mut [u8]) -> Result<(), Error> {
+        self.check_b2b(plaintext, ciphertext.len())?;
+        ciphertext.copy_from_slice(plaintext);
+        self.process(ciphertext);
+        Ok(())
+    }
+
+    /// Decrypts `ciphertext` into `plaintext`, same errors as `encrypt_b2b`
+    pub fn decrypt_b2b(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<(), Error> {
+        self.encrypt_b2b(ciphertext, plaintext)
+    }
+
+    /// `encrypt_b2b` into a buffer that may not be initialized yet, returns
+    /// the ciphertext written to it
+    pub fn encrypt_b2b_uninit<'a>(&mut self, plaintext: &[u8], ciphertext: &'a mut [MaybeUninit<u8>]) -> Result<&'a mut [u8], Error> {
+        self.check_b2b(plaintext, ciphertext.len())?;
+        let ciphertext = copy_to_uninit(plaintext, ciphertext);
+        self.process(ciphertext);
+        Ok(ciphertext)
+    }
+
+    /// `decrypt_b2b` into a buffer that may not be initialized yet, returns
+    /// the plaintext written to it
+    pub fn decrypt_b2b_uninit<'a>(&mut self, ciphertext: &[u8], plaintext: &'a mut [MaybeUninit<u8>]) -> Result<&'a mut [u8], Error> {
+        self.encrypt_b2b_uninit(ciphertext, plaintext)
+    }
+
+    fn check_b2b(&self, src: &[u8], dst_len: usize) -> Result<(), Error> {
+        if src.len() != dst_len {
+            return Err(Error::InvalidLength);
+        }
+        if src.len() as u64 > self.remaining() {
+            return Err(Error::LimitExceeded);
         }
+        Ok(())
     }
 }
 
 
+#[cfg(feature = "std")]
+impl<const R: usize, const LEGACY: bool> ChaCha<R, LEGACY> {
+    /// Encrypts the segments of `bufs` as one contiguous buffer, the
+    /// keystream carries on across segment boundaries
+    ///
+    /// # Panics
+    /// If the keystream runs out, see `try_encrypt_vectored`
+    pub fn encrypt_vectored(&mut self, bufs: &mut [std::io::IoSliceMut<'_>]) {
+        self.try_encrypt_vectored(bufs).expect("ChaCha20 keystream exhausted");
+    }
+
+    /// # Panics
+    /// If the keystream runs out, see `try_decrypt_vectored`
+    pub fn decrypt_vectored(&mut self, bufs: &mut [std::io::IoSliceMut<'_>]) {
+        self.try_decrypt_vectored(bufs).expect("ChaCha20 keystream exhausted");
+    }
+
+    /// Fallible `encrypt_vectored`, returns `Error::LimitExceeded` without
+    /// touching any segment if the keystream left is too short
+    pub fn try_encrypt_vectored(&mut self, bufs: &mut [std::io::IoSliceMut<'_>]) -> Result<(), Error> {
+        let len: u64 = bufs.iter().map(|buf| buf.len() as u64).sum();
+        if len > self.remaining() {
+            return Err(Error::LimitExceeded);
+        }
+        for buf in bufs {
+            self.process(buf);
+        }
+        Ok(())
+    }
+
+    /// Fallible `decrypt_vectored`, returns `Error::LimitExceeded` without
+    /// touching any segment if the keystream left is too short
+    pub fn try_decrypt_vectored(&mut self, bufs: &mut [std::io::IoSliceMut<'_>]) -> Result<(), Error> {
+        self.try_encrypt_vectored(bufs)
+    }
+}
+
+
+impl<const R: usize, const LEGACY: bool> Zeroize for ChaCha<R, LEGACY> {
+    fn zeroize(&mut self) {
+        self.state.as_mut_array().zeroize();
+        self.keystream.as_mut_array().zeroize();
+        self.keystream_buffer.as_mut_array().zeroize();
+        self.offset.zeroize();
+        self.exhausted.zeroize();
+    }
+}
+
+impl<const R: usize, const LEGACY: bool> Drop for ChaCha<R, LEGACY> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<const R: usize, const LEGACY: bool> ZeroizeOnDrop for ChaCha<R, LEGACY> {}
+
+
+impl ChaCha20 {
+    /// XChaCha20: the 24 bytes nonce is split into a HChaCha20 subkey
+    /// derivation on its first 16 bytes and a regular nonce for the rest
+    pub fn new_xchacha20(key: &[u8; 32], nonce: &[u8; 24]) -> Self {
+        let (subkey, nonce) = xchacha20_subkey(key, nonce);
+        Self::new(&subkey, &nonce)
+    }
+}
+
+
+/// Copies `src` into `dst`, of the same length, and returns it initialized
+pub(crate) fn copy_to_uninit<'a>(src: &[u8], dst: &'a mut [MaybeUninit<u8>]) -> &'a mut [u8] {
+    assert_eq!(src.len(), dst.len());
+    // SAFETY: both are `src.len()` bytes long and can't overlap, `dst` being
+    // borrowed mutably; once written to, its bytes are initialized
+    unsafe {
+        src.as_ptr().copy_to_nonoverlapping(dst.as_mut_ptr().cast(), src.len());
+        &mut *(dst as *mut [MaybeUninit<u8>] as *mut [u8])
+    }
+}
+
+/// HChaCha20 subkey derivation (draft-irtf-cfrg-xchacha section 2.2).
+/// The first 4 bytes of `nonce` take the place of the block counter and
+/// only the first and last rows of the permuted state are kept
+pub fn hchacha20(key: &[u8; 32], nonce: &[u8; 16]) -> [u8; 32] {
+    let counter   = u32_from_le_bytes(&nonce[..4]);
+    let mut state = ChaCha20::new_with_counter(key, nonce[4..].try_into().unwrap(), counter).state;
+    ChaCha20::rounds(&mut state);
+
+    let mut subkey = [0u8; 32];
+    for (i, word) in state[0..4].iter().chain(&state[12..16]).enumerate() {
+        subkey[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    state.as_mut_array().zeroize();
+    subkey
+}
+
+/// Splits an XChaCha20 nonce into the HChaCha20 subkey and the 12 bytes
+/// nonce to use with it
+pub(crate) fn xchacha20_subkey(key: &[u8; 32], nonce: &[u8; 24]) -> (Zeroizing<[u8; 32]>, [u8; 12]) {
+    let subkey = Zeroizing::new(hchacha20(key, nonce[..16].try_into().unwrap()));
+    let mut ietf_nonce = [0u8; 12];
+    ietf_nonce[4..].copy_from_slice(&nonce[16..]);
+    (subkey, ietf_nonce)
+}
+
+
 /// Method to convert to u32
 /// This should never panic since we are sure of size we pass to method
 fn u32_from_le_bytes(slice: &[u8]) -> u32 {
@@ -126,10 +550,21 @@ fn u32_from_le_bytes(slice: &[u8]) -> u32 {
 
 #[cfg(test)]
 mod tests {
+    use std::{mem::MaybeUninit, ptr};
+
     use hex_literal::hex;
     use rand::RngCore;
 
-    use super::ChaCha20;
+    use crate::{test_util::{contains, field, fields_after_drop}, Backend, CipherState, Error};
+    use super::{hchacha20, ChaCha8, ChaCha12, ChaCha20, ChaCha20Legacy};
+
+    /// Key and keystream fields of a cipher
+    fn secret_fields(cipher: *const ChaCha20) -> Vec<(*const u8, usize)> {
+        // SAFETY: only computes field addresses
+        unsafe {
+            vec![field(ptr::addr_of!((*cipher).state)), field(ptr::addr_of!((*cipher).keystream)), field(ptr::addr_of!((*cipher).keystream_buffer))]
+        }
+    }
 
     #[test]
     fn validate() {
@@ -151,9 +586,9 @@ mod tests {
 
         assert_eq!(buffer1, hex!("e3647a29ded31528ef56bac70f7a7ac3b735c7444da42d99823ef9938c8ebfdcf05bb71a822c62981aa1ea608f47933f2ed755b62d9312ae72037674f3e93e244c2328d32f75bcc15bb7574fde0c6fcdf87b7aa25b5972970c2ae6cced86a10be9496fc61c407dfdc01510ed8f4eb35d0d62"));
 
-        assert_eq!(buffer2, hex!("25c710f65a102f204caede6f8923b01243b9510350d6ea538781a01aa76038364aab01bc3aa415b0fb47a3abb0545e489c161867534f7cc7ed899c9156e4e482a6817f6c01fdf860451c9ec1213f81c5ddc6ee66a738d93fe44de68f753f4fa3690036c896eff9dfed005a4810a3fe0c39ae"));
+        assert_eq!(buffer2, hex!("f621a25751beca404228d03a24d91dca11f25a0d2f2e44ea8a40896da71247a7474d1fdfac54cad0ff1a8d6a790c19e801af76e70ce6a7018fbfb064115ed30c1a6f0a0075cfeddbc88149b1a282bacd72240bb3fb3545009c846b708090c7dde834a428c36ae147bfc3626a43b52a1b2783"));
 
-        assert_eq!(buffer3, hex!("e2b2918e9ffe293d5e01676f500ab8ff0492462b0dd8a056111de080861d1a72baad5228a3e09fe296d56d86e4db61cce87e7f1735ca431f85a3650416d4ed49a53dcc8272d35d28981e21cbff5fa1782ba95fe7d135c7787a63762ca1344fde520dd227cec12e12e8a5714f628d75ccbdfa"));
+        assert_eq!(buffer3, hex!("b4aeead9fd1f1e091ca2fe2228ee02a7ffccf9796152e09c7abd0e33cdbf949489ad663a10427e110f4485f541b6032642c2aa12451ae3c680035b78a6f81d21edabdffbc39b41c0b0fb318fe164615361cd405986a431080bc4e106b824dbd17ecf5d6d82572684ec5cb7783da41aaec36e"));
 
         cipher1.decrypt(&mut buffer1);
         cipher1.decrypt(&mut buffer2);
@@ -189,4 +624,392 @@ mod tests {
             assert_eq!(msg, b"hello".repeat(300));
         }
     }
+
+    #[test]
+    fn streaming() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let msg = b"hello".repeat(300);
+        let mut expected = msg.clone();
+        ChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        for sizes in [[10, 10, 10], [1, 63, 64], [64, 1, 200], [100, 28, 3]] {
+            let mut cipher = ChaCha20::new(&key, &nonce);
+            let mut buffer = msg.clone();
+            let mut pos = 0;
+            for size in sizes.iter().cycle() {
+                let end = (pos + size).min(buffer.len());
+                cipher.encrypt(&mut buffer[pos..end]);
+                pos = end;
+                assert_eq!(cipher.current_pos(), pos as u64);
+                if pos == buffer.len() {
+                    break;
+                }
+            }
+            assert_eq!(buffer, expected);
+        }
+    }
+
+    #[test]
+    fn initial_counter() {
+        // RFC 8439 section 2.4.2
+        let key = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        let nonce = hex!("00 00 00 00 00 00 00 4a 00 00 00 00");
+
+        let mut buffer = "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".as_bytes().to_vec();
+
+        let mut cipher = ChaCha20::new_with_counter(&key, &nonce, 1);
+        assert_eq!(cipher.current_pos(), 64);
+        cipher.encrypt(&mut buffer);
+        assert_eq!(cipher.current_pos(), 64 + 114);
+
+        assert_eq!(buffer, hex!("6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d"));
+
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        cipher.seek(64);
+        cipher.decrypt(&mut buffer);
+        assert_eq!(buffer, "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".as_bytes());
+    }
+
+    #[test]
+    fn seek() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let msg = b"hello".repeat(300);
+        let mut ciphertext = msg.clone();
+        ChaCha20::new(&key, &nonce).encrypt(&mut ciphertext);
+
+        for offset in [0, 1, 63, 64, 65, 127, 500, 1024, 1499] {
+            let mut cipher = ChaCha20::new(&key, &nonce);
+            cipher.seek(offset as u64);
+            assert_eq!(cipher.current_pos(), offset as u64);
+
+            let mut buffer = ciphertext[offset..].to_vec();
+            cipher.decrypt(&mut buffer);
+            assert_eq!(buffer, msg[offset..]);
+        }
+
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        cipher.seek(64 * u32::MAX as u64 + 63);
+        assert_eq!(cipher.current_pos(), 64 * u32::MAX as u64 + 63);
+        assert_eq!(cipher.remaining(), 1);
+    }
+
+    #[test]
+    fn limit() {
+        let key = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        let nonce = hex!("00 00 00 00 00 00 00 4a 00 00 00 00");
+
+        let cipher = ChaCha20::new(&key, &nonce);
+        assert_eq!(cipher.remaining(), 64 << 32);
+
+        let mut cipher = ChaCha20::new_with_counter(&key, &nonce, u32::MAX - 1);
+        assert_eq!(cipher.remaining(), 128);
+
+        let mut buffer = [0u8; 129];
+        assert_eq!(cipher.try_encrypt(&mut buffer), Err(Error::LimitExceeded));
+        assert_eq!(buffer, [0u8; 129]);
+
+        cipher.try_encrypt(&mut buffer[..70]).unwrap();
+        assert_eq!(cipher.remaining(), 58);
+        cipher.try_encrypt(&mut buffer[70..128]).unwrap();
+        assert_eq!(cipher.remaining(), 0);
+        assert_eq!(cipher.current_pos(), 64 << 32);
+        assert_eq!(cipher.try_encrypt(&mut buffer[128..]), Err(Error::LimitExceeded));
+        assert_eq!(cipher.try_decrypt(&mut buffer[128..]), Err(Error::LimitExceeded));
+        // empty input is still fine at the end of the keystream
+        cipher.try_encrypt(&mut []).unwrap();
+
+        // last two blocks of the keystream
+        assert_eq!(buffer[..128], hex!("143d2a137837a2a369b90769dd68f5ae394a28786b03f80c2a1e8d3d1ebdf4f0181e597e89f42939e94c717d60b681d34cf82dda79827ab2455b13428e525fd96d29da5bd16a472910e8c0bdb47edfc8499c3222cc168d3721747fc2b21266d9f15c8339f10f354d16cc9b8e118eb182bf858ce5718fa4e76389ea4eb50a9475"));
+
+        cipher.seek(0);
+        assert_eq!(cipher.remaining(), 64 << 32);
+    }
+
+    #[test]
+    #[should_panic(expected = "keystream exhausted")]
+    fn limit_panics() {
+        let mut cipher = ChaCha20::new_with_counter(&[0u8; 32], &[0u8; 12], u32::MAX);
+        cipher.encrypt(&mut [0u8; 65]);
+    }
+
+    #[test]
+    fn hchacha20_subkey() {
+        // draft-irtf-cfrg-xchacha section 2.2.1
+        let key = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        let nonce = hex!("00 00 00 09 00 00 00 4a 00 00 00 00 31 41 59 27");
+
+        assert_eq!(hchacha20(&key, &nonce), hex!("82413b4227b27bfed30e42508a877d73a0f9e4d58a74a853c12ec41326d3ecdc"));
+    }
+
+    #[test]
+    fn xchacha20() {
+        // draft-irtf-cfrg-xchacha appendix A.3.2
+        let key = hex!("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = hex!("404142434445464748494a4b4c4d4e4f5051525354555658");
+
+        let msg = b"The dhole (pronounced \"dole\") is also known as the Asiatic wild dog, red dog, and whistling dog. It is about the size of a German shepherd but looks more like a long-legged fox. This highly elusive and skilled jumper is classified with wolves, coyotes, jackals, and foxes in the taxonomic family Canidae.".to_vec();
+
+        let mut buffer = msg.clone();
+        ChaCha20::new_xchacha20(&key, &nonce).encrypt(&mut buffer);
+
+        assert_eq!(buffer, hex!("4559abba4e48c16102e8bb2c05e6947f50a786de162f9b0b7e592a9b53d0d4e98d8d6410d540a1a6375b26d80dace4fab52384c731acbf16a5923c0c48d3575d4d0d2c673b666faa731061277701093a6bf7a158a8864292a41c48e3a9b4c0daece0f8d98d0d7e05b37a307bbb66333164ec9e1b24ea0d6c3ffddcec4f68e7443056193a03c810e11344ca06d8ed8a2bfb1e8d48cfa6bc0eb4e2464b748142407c9f431aee769960e15ba8b96890466ef2457599852385c661f752ce20f9da0c09ab6b19df74e76a95967446f8d0fd415e7bee2a12a114c20eb5292ae7a349ae577820d5520a1f3fb62a17ce6a7e68fa7c79111d8860920bc048ef43fe84486ccb87c25f0ae045f0cce1e7989a9aa220a28bdd4827e751a24a6d5c62d790a66393b93111c1a55dd7421a10184974c7c5"));
+
+        ChaCha20::new_xchacha20(&key, &nonce).decrypt(&mut buffer);
+        assert_eq!(buffer, msg);
+    }
+
+    #[test]
+    fn reduced_rounds() {
+        let key = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        let nonce = hex!("00 00 00 00 00 00 00 4a 00 00 00 00");
+
+        let mut buffer = [0u8; 128];
+        ChaCha8::new(&key, &nonce).encrypt(&mut buffer);
+        assert_eq!(buffer, hex!("5a6e23747061917868d31dc7c1e7203a98b59ada28b2bd8224cb22c7a5f4ad4c25164778375760dbe4a871d1d15530593db7d8f154e6687b73acc465e7aa5fd0bc08fed3f82c571c5e7a70866588aee281ee18680869a9c2af9f4e244a4a563761b2dfe8a747dafd532f8496553311589abd3ec1eb4576054477a7295b82cbb7"));
+
+        let mut buffer = [0u8; 128];
+        ChaCha12::new(&key, &nonce).encrypt(&mut buffer);
+        assert_eq!(buffer, hex!("631c0ceaad4a393c070ed70ca805409e22aa63e516c26b9fd8f770d1d58356637f66bafb595ddda4c516742e0dbcca80f6148412a7f94130c990837f9d82abeec126863f9577559308796ff81a44655bd352630c35bd4beccbad4b6fdd7b608f8ba8301c3a1e8f0643571dbe21583d5f622a60f4321e1243b88a4796306f9122"));
+
+        let mut cipher = ChaCha12::new(&key, &nonce);
+        cipher.seek(64);
+        cipher.decrypt(&mut buffer[64..]);
+        assert_eq!(buffer[64..], [0u8; 64]);
+    }
+
+    #[test]
+    fn backends() {
+        let key = [7u8; 32];
+        let nonce = [9u8; 12];
+
+        // one block at a time never goes through the backends
+        let mut expected = vec![0u8; 64 * 45 + 17];
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        for chunk in expected.chunks_mut(64) {
+            cipher.encrypt(chunk);
+        }
+
+        assert_eq!(ChaCha20::new(&key, &nonce).backend(), Backend::detect());
+        for backend in Backend::ALL.into_iter().filter(|b| b.is_supported()) {
+            for len in [256, 300, 512, 768, 1024, 64 * 12 + 5, 64 * 28, expected.len()] {
+                let mut buffer = vec![0u8; len];
+                let mut cipher = ChaCha20::new(&key, &nonce);
+                cipher.set_backend(backend);
+                cipher.encrypt(&mut buffer);
+                assert_eq!(buffer, expected[..len], "{backend:?}");
+            }
+
+            // starting mid block
+            let mut buffer = vec![0u8; 2000];
+            let mut cipher = ChaCha20::new(&key, &nonce);
+            cipher.set_backend(backend);
+            cipher.encrypt(&mut buffer[..3]);
+            cipher.encrypt(&mut buffer[3..]);
+            assert_eq!(buffer, expected[..2000], "{backend:?}");
+
+            // last 16 blocks of the 32 bit counter
+            let mut cipher = ChaCha20::new_with_counter(&key, &nonce, u32::MAX - 15);
+            cipher.set_backend(backend);
+            let mut buffer = [0u8; 1024];
+            cipher.encrypt(&mut buffer);
+            let mut single = ChaCha20::new_with_counter(&key, &nonce, u32::MAX - 15);
+            for chunk in buffer.chunks_mut(64) {
+                single.decrypt(chunk);
+            }
+            assert_eq!(buffer, [0u8; 1024], "{backend:?}");
+            assert_eq!(cipher.remaining(), 0);
+
+            // 64 bit counter carrying in the middle of the wide blocks
+            for start in [u32::MAX as u64 - 2, u32::MAX as u64 - 5, u32::MAX as u64 - 13] {
+                let mut cipher = ChaCha20Legacy::new_with_counter(&key, &[9u8; 8], start);
+                cipher.set_backend(backend);
+                let mut buffer = [0u8; 1024];
+                cipher.encrypt(&mut buffer);
+                let mut single = ChaCha20Legacy::new_with_counter(&key, &[9u8; 8], start);
+                for chunk in buffer.chunks_mut(64) {
+                    single.decrypt(chunk);
+                }
+                assert_eq!(buffer, [0u8; 1024], "{backend:?}");
+                assert_eq!(cipher.current_pos(), (start + 16) * 64);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "backend not supported")]
+    fn unsupported_backend() {
+        let backend = Backend::ALL.into_iter().find(|b| !b.is_supported()).unwrap();
+        ChaCha20::new(&[0u8; 32], &[0u8; 12]).set_backend(backend);
+    }
+
+    #[test]
+    fn legacy() {
+        // draft-strombergson-chacha-test-vectors TC8, 20 rounds
+        let key = hex!("c46ec1b18ce8a878725a37e780dfb7351f68ed2e194c79fbc6aebee1a667975d");
+        let nonce = hex!("1a da 31 d5 cf 68 82 21");
+
+        let mut buffer = [0u8; 128];
+        ChaCha20Legacy::new(&key, &nonce).encrypt(&mut buffer);
+        assert_eq!(buffer, hex!("f63a89b75c2271f9368816542ba52f06ed49241792302b00b5e8f80ae9a473afc25b218f519af0fdd406362e8d69de7f54c604a6e00f353f110f771bdca8ab92e5fbc34e60a1d9a9db17345b0a402736853bf910b060bdf1f897b6290f01d138ae2c4c90225ba9ea14d518f55929dea098ca7a6ccfe61227053c84e49a4a3332"));
+
+        // counter carries into word 13 instead of running out
+        let key = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        let nonce = hex!("00 00 00 00 00 00 00 4a");
+        let expected = hex!("5ac635c23440ac375aa7fd28de550428b3af38c7a5c7026a9eccc31aeea51ae2023908a4a1c1f6a5c1c8820936878652ec585fdcb72df00c1583d0efea883ce196f0ec7f1aac687f5ad56a86e52fa52948e66935d41fd29a6cc6e3c8dac30946ce7af11bea3bc9278bc3a917c6fa9ee8c1f3c13e8f2f1bbf34ce5f41df114676");
+
+        let mut cipher = ChaCha20Legacy::new_with_counter(&key, &nonce, u32::MAX as u64);
+        let mut buffer = [0u8; 128];
+        cipher.encrypt(&mut buffer);
+        assert_eq!(buffer, expected);
+        assert_eq!(cipher.current_pos(), (64 << 32) + 64);
+
+        let mut cipher = ChaCha20Legacy::new(&key, &nonce);
+        cipher.seek((64 << 32) - 10);
+        cipher.decrypt(&mut buffer[54..]);
+        assert_eq!(buffer[54..], [0u8; 74]);
+
+        let mut cipher = ChaCha20Legacy::new_with_counter(&key, &nonce, u64::MAX);
+        assert_eq!(cipher.remaining(), 64);
+        assert_eq!(cipher.try_encrypt(&mut [0u8; 65]), Err(Error::LimitExceeded));
+        cipher.try_encrypt(&mut [0u8; 64]).unwrap();
+        assert_eq!(cipher.remaining(), 0);
+
+        assert_eq!(ChaCha20Legacy::new(&key, &nonce).remaining(), u64::MAX);
+    }
+
+    #[test]
+    fn b2b() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let msg = b"hello".repeat(300);
+        let mut expected = msg.clone();
+        ChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        let mut ciphertext = vec![0u8; msg.len()];
+        cipher.encrypt_b2b(&msg[..10], &mut ciphertext[..10]).unwrap();
+        cipher.encrypt_b2b(&msg[10..], &mut ciphertext[10..]).unwrap();
+        assert_eq!(ciphertext, expected);
+
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        let mut plaintext = vec![MaybeUninit::uninit(); msg.len()];
+        assert_eq!(cipher.decrypt_b2b_uninit(&expected, &mut plaintext).unwrap(), msg);
+
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        let mut plaintext = vec![0u8; msg.len()];
+        assert_eq!(cipher.decrypt_b2b(&expected, &mut plaintext[1..]), Err(Error::InvalidLength));
+        assert_eq!(cipher.decrypt_b2b_uninit(&expected, &mut []).err(), Some(Error::InvalidLength));
+        assert_eq!(cipher.current_pos(), 0);
+
+        cipher.seek(64 * u32::MAX as u64);
+        assert_eq!(cipher.decrypt_b2b(&expected, &mut plaintext), Err(Error::LimitExceeded));
+        assert_eq!(plaintext, vec![0u8; msg.len()]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn vectored() {
+        use std::io::IoSliceMut;
+
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let msg = b"hello".repeat(300);
+        let mut expected = msg.clone();
+        ChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        let mut buffer = msg.clone();
+        let (a, rest) = buffer.split_at_mut(3);
+        let (b, rest) = rest.split_at_mut(0);
+        let (c, d) = rest.split_at_mut(700);
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        cipher.encrypt_vectored(&mut [IoSliceMut::new(a), IoSliceMut::new(b), IoSliceMut::new(c), IoSliceMut::new(d)]);
+        assert_eq!(buffer, expected);
+
+        let (a, b) = buffer.split_at_mut(65);
+        let mut cipher = ChaCha20::new(&key, &nonce);
+        cipher.decrypt_vectored(&mut [IoSliceMut::new(a), IoSliceMut::new(b)]);
+        assert_eq!(buffer, msg);
+
+        let mut cipher = ChaCha20::new_with_counter(&key, &nonce, u32::MAX);
+        let (a, b) = buffer.split_at_mut(60);
+        assert_eq!(cipher.try_encrypt_vectored(&mut [IoSliceMut::new(a), IoSliceMut::new(b)]), Err(Error::LimitExceeded));
+        assert_eq!(buffer, msg);
+    }
+
+    #[test]
+    fn export_state() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let msg = b"hello".repeat(300);
+        let mut expected = msg.clone();
+        ChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        for split in [0, 1, 63, 64, 65, 500, 1499, 1500] {
+            let mut cipher = ChaCha20::new(&key, &nonce);
+            let mut buffer = msg.clone();
+            cipher.encrypt(&mut buffer[..split]);
+
+            let bytes = cipher.export_state().as_bytes().to_vec();
+            drop(cipher);
+            let mut cipher = ChaCha20::from_state(&CipherState::from_bytes(&bytes).unwrap()).unwrap();
+            assert_eq!(cipher.current_pos(), split as u64);
+            cipher.encrypt(&mut buffer[split..]);
+            assert_eq!(buffer, expected);
+        }
+
+        let mut cipher = ChaCha20Legacy::new_with_counter(&key, &[7u8; 8], u32::MAX as u64);
+        cipher.encrypt(&mut [0u8; 70]);
+        let mut resumed = ChaCha20Legacy::from_state(&cipher.export_state()).unwrap();
+        let (mut a, mut b) = ([0u8; 100], [0u8; 100]);
+        cipher.encrypt(&mut a);
+        resumed.encrypt(&mut b);
+        assert_eq!(a, b);
+
+        let mut cipher = ChaCha20::new_with_counter(&key, &nonce, u32::MAX);
+        cipher.encrypt(&mut [0u8; 64]);
+        let resumed = ChaCha20::from_state(&cipher.export_state()).unwrap();
+        assert_eq!(resumed.remaining(), 0);
+
+        let state = ChaCha20::new(&key, &nonce).export_state();
+        assert_eq!(ChaCha12::from_state(&state).err(), Some(Error::InvalidState));
+        assert_eq!(ChaCha20Legacy::from_state(&state).err(), Some(Error::InvalidState));
+    }
+
+    #[test]
+    fn zeroize_on_drop() {
+        let key = [0xa5u8; 32];
+        let nonce = [0x5au8; 12];
+        let mut keystream = [0u8; 64];
+        ChaCha20::new(&key, &nonce).encrypt(&mut keystream);
+
+        // leaves the rest of block 0 buffered
+        let (live, dropped) = fields_after_drop(ChaCha20::new(&key, &nonce), |cipher| cipher.encrypt(&mut [0u8; 10]), secret_fields);
+
+        assert!(contains(&live, &key[..16]));
+        assert!(contains(&live, &keystream[32..48]));
+        assert!(!contains(&dropped, &key[..16]));
+        assert!(!contains(&dropped, &keystream[32..48]));
+    }
 }
@@ -0,0 +1,146 @@
+use zeroize::Zeroizing;
+
+use crate::{chacha20::ChaCha20, error::Error, poly1305::Poly1305};
+
+/// ChaCha20-Poly1305 AEAD as described in RFC 8439 section 2.8
+pub struct ChaCha20Poly1305 {
+    key: Zeroizing<[u8; 32]>
+}
+
+impl ChaCha20Poly1305 {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            key: Zeroizing::new(*key)
+        }
+    }
+
+    /// Sets up the cipher for `nonce` and derives the one-time Poly1305 key
+    /// from block 0, leaving the cipher at block 1 for the message itself
+    fn init(&self, nonce: &[u8; 12]) -> (ChaCha20, Poly1305) {
+        let mut cipher = ChaCha20::new(&self.key, nonce);
+        let mut block0 = Zeroizing::new([0u8; 64]);
+        cipher.encrypt(block0.as_mut_slice());
+
+        let poly = Poly1305::new(block0[..32].try_into().unwrap());
+        (cipher, poly)
+    }
+
+    fn compute_tag(mut poly: Poly1305, aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+        poly.update(aad);
+        poly.pad();
+        poly.update(ciphertext);
+        poly.pad();
+        poly.update(&(aad.len() as u64).to_le_bytes());
+        poly.update(&(ciphertext.len() as u64).to_le_bytes());
+        poly.finalize()
+    }
+
+    /// Encrypts `buffer` in place and returns the authentication tag
+    /// covering both `aad` and the ciphertext
+    ///
+    /// # Panics
+    /// If `buffer` is longer than the 2^38 - 64 bytes RFC 8439 allows, see `try_encrypt`
+    pub fn encrypt(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8]) -> [u8; 16] {
+        self.try_encrypt(nonce, aad, buffer).expect("ChaCha20 keystream exhausted")
+    }
+
+    /// Fallible `encrypt`, returns `Error::LimitExceeded` without touching
+    /// `buffer` if it is longer than the 2^38 - 64 bytes RFC 8439 allows
+    pub fn try_encrypt(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8]) -> Result<[u8; 16], Error> {
+        let (mut cipher, poly) = self.init(nonce);
+        cipher.try_encrypt(buffer)?;
+        Ok(Self::compute_tag(poly, aad, buffer))
+    }
+
+    /// Checks `tag` and decrypts `buffer` in place.
+    /// On mismatch `buffer` is left untouched and `Error::TagMismatch` is returned
+    pub fn decrypt(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8], tag: &[u8; 16]) -> Result<(), Error> {
+        let (mut cipher, poly) = self.init(nonce);
+        let expected = Self::compute_tag(poly, aad, buffer);
+        if !ct_eq(&expected, tag) {
+            return Err(Error::TagMismatch);
+        }
+        cipher.try_decrypt(buffer)
+    }
+}
+
+
+/// Constant time tag comparison
+pub(crate) fn ct_eq(a: &[u8; 16], b: &[u8; 16]) -> bool {
+    let diff = a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    core::hint::black_box(diff) == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use rand::RngCore;
+
+    use crate::Error;
+    use super::ChaCha20Poly1305;
+
+    #[test]
+    fn validate() {
+        // RFC 8439 section 2.8.2
+        let key = hex!("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = hex!("07 00 00 00 40 41 42 43 44 45 46 47");
+        let aad = hex!("50 51 52 53 c0 c1 c2 c3 c4 c5 c6 c7");
+
+        let msg = "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".as_bytes().to_vec();
+
+        let aead = ChaCha20Poly1305::new(&key);
+        let mut buffer = msg.clone();
+        let tag = aead.encrypt(&nonce, &aad, &mut buffer);
+
+        assert_eq!(buffer, hex!("d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b6116"));
+        assert_eq!(tag, hex!("1ae10b594f09e26a7e902ecbd0600691"));
+
+        aead.decrypt(&nonce, &aad, &mut buffer, &tag).unwrap();
+        assert_eq!(buffer, msg);
+    }
+
+    #[test]
+    fn rfc8439_appendix_a5() {
+        let key = hex!("1c9240a5eb55d38af333888604f6b5f0473917c1402b80099dca5cbc207075c0");
+        let nonce = hex!("00 00 00 00 01 02 03 04 05 06 07 08");
+        let aad = hex!("f3 33 88 86 00 00 00 00 00 00 4e 91");
+        let tag = hex!("eead9d67890cbb22392336fea1851f38");
+
+        let mut buffer = hex!("64a0861575861af460f062c79be643bd5e805cfd345cf389f108670ac76c8cb24c6cfc18755d43eea09ee94e382d26b0bdb7b73c321b0100d4f03b7f355894cf332f830e710b97ce98c8a84abd0b948114ad176e008d33bd60f982b1ff37c8559797a06ef4f0ef61c186324e2b3506383606907b6a7c02b0f9f6157b53c867e4b9166c767b804d46a59b5216cde7a4e99040c5a40433225ee282a1b0a06c523eaf4534d7f83fa1155b0047718cbc546a0d072b04b3564eea1b422273f548271a0bb2316053fa76991955ebd63159434ecebb4e466dae5a1073a6727627097a1049e617d91d361094fa68f0ff77987130305beaba2eda04df997b714d6c6f2c29a6ad5cb4022b02709b").to_vec();
+
+        let aead = ChaCha20Poly1305::new(&key);
+        aead.decrypt(&nonce, &aad, &mut buffer, &tag).unwrap();
+
+        assert_eq!(buffer, "Internet-Drafts are draft documents valid for a maximum of six months and may be updated, replaced, or obsoleted by other documents at any time. It is inappropriate to use Internet-Drafts as reference material or to cite them other than as /\u{201c}work in progress./\u{201d}".as_bytes());
+    }
+
+    #[test]
+    fn tampering() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let aead = ChaCha20Poly1305::new(&key);
+        let msg  = b"hello".repeat(30);
+        let mut buffer = msg.clone();
+        let tag = aead.encrypt(&nonce, b"header", &mut buffer);
+        let ciphertext = buffer.clone();
+
+        buffer[17] ^= 1;
+        assert_eq!(aead.decrypt(&nonce, b"header", &mut buffer, &tag), Err(Error::TagMismatch));
+        buffer[17] ^= 1;
+        assert_eq!(buffer, ciphertext);
+
+        assert_eq!(aead.decrypt(&nonce, b"headex", &mut buffer, &tag), Err(Error::TagMismatch));
+
+        let mut bad_tag = tag;
+        bad_tag[0] ^= 0x80;
+        assert_eq!(aead.decrypt(&nonce, b"header", &mut buffer, &bad_tag), Err(Error::TagMismatch));
+
+        aead.decrypt(&nonce, b"header", &mut buffer, &tag).unwrap();
+        assert_eq!(buffer, msg);
+    }
+}
@@ -0,0 +1,747 @@
+//! Encrypted container format, for files and other byte streams.
+//!
+//! ```text
+//! header, 37 bytes, 66 for password protected containers
+//! offset  len  field
+//!      0    8  magic, "DCHACHA\x1a"
+//!      8    1  format version, 1 with a key, 2 with a password
+//!      9    1  algorithm, 1 = ChaCha20-Poly1305, 2 = DChaCha20Aead
+//!     10    4  chunk size, plaintext bytes per chunk, little endian
+//!     14   23  nonce, 16 bytes of subkey salt then the 7 bytes chunk nonce prefix
+//! version 2 only, integers little endian:
+//!     37    1  key derivation, 1 = Argon2id, 2 = scrypt
+//!     38   12  Argon2id memory (KiB), passes, lanes or scrypt log2(N), r, p
+//!     50   16  salt
+//!
+//! chunks, one after the other
+//!      0    1  1 for the final chunk, 0 otherwise
+//!      1    n  ciphertext, chunk size bytes, 0 up to chunk size for the final chunk
+//!    1+n   16  tag
+//! ```
+//!
+//! Chunks are sealed under a subkey, `HChaCha20(key, subkey salt)` as in
+//! XChaCha20, so that a random nonce can be picked for every container of a
+//! key: 23 random bytes only make a collision likely after some 2^92
+//! containers, where 7 would after 2^28.
+//!
+//! Every chunk is sealed on its own, with the whole header as associated data
+//! and `nonce prefix || chunk index (u32 big endian) || final flag` as nonce,
+//! so chunks can't be moved, dropped, or taken from another algorithm or
+//! container. The final chunk, possibly empty, is what tells a complete
+//! container from a truncated one, nothing may follow it.
+//!
+//! Only AEADs are offered: a plain ChaCha20 or DChaCha20 stream would have
+//! nothing to compute the chunk tags with.
+//!
+//! With the `password` feature the key can be derived from a password, see
+//! `Kdf` for the cost parameters accepted.
+
+use std::io::{self, Read, Write};
+
+use zeroize::Zeroizing;
+
+use crate::{chacha20::hchacha20, chacha20poly1305::ChaCha20Poly1305, dchacha20aead::DChaCha20Aead, error::Error};
+#[cfg(feature = "password")]
+use crate::kdf::{Kdf, SALT_LEN};
+
+/// Identifies a container
+const MAGIC: [u8; 8] = *b"DCHACHA\x1a";
+/// Version of the container format for a key
+const VERSION: u8 = 1;
+/// Version of the container format for a password, adds the key derivation
+/// parameters and salt to the header
+const PASSWORD_VERSION: u8 = 2;
+/// Length of the nonce in the container header
+pub const NONCE_LEN: usize = 23;
+/// Length of the container header
+const HEADER_LEN: usize = 14 + NONCE_LEN;
+/// Length of the container header with a password
+#[cfg(feature = "password")]
+const PASSWORD_HEADER_LEN: usize = HEADER_LEN + 13 + SALT_LEN;
+/// Largest chunk size, bounds what a reader allocates for a chunk
+pub const MAX_CHUNK_SIZE: u32 = 1 << 24;
+
+/// AEAD sealing the chunks of a container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Algorithm {
+    ChaCha20Poly1305 = 1,
+    DChaCha20Aead = 2
+}
+
+impl Algorithm {
+    fn from_u8(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Algorithm::ChaCha20Poly1305),
+            2 => Some(Algorithm::DChaCha20Aead),
+            _ => None
+        }
+    }
+}
+
+enum Aead {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    DChaCha20Aead(DChaCha20Aead)
+}
+
+impl Aead {
+    /// AEAD keyed with the subkey of `key` for the container of `header`
+    fn new(algorithm: Algorithm, key: &[u8; 32], header: &[u8]) -> Self {
+        let subkey = Zeroizing::new(hchacha20(key, header[14..30].try_into().unwrap()));
+        match algorithm {
+            Algorithm::ChaCha20Poly1305 => Aead::ChaCha20Poly1305(ChaCha20Poly1305::new(&subkey)),
+            Algorithm::DChaCha20Aead => Aead::DChaCha20Aead(DChaCha20Aead::new(&subkey))
+        }
+    }
+
+    fn encrypt(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8]) -> [u8; 16] {
+        match self {
+            Aead::ChaCha20Poly1305(aead) => aead.encrypt(nonce, aad, buffer),
+            Aead::DChaCha20Aead(aead) => aead.encrypt(nonce, aad, buffer)
+        }
+    }
+
+    fn decrypt(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8], tag: &[u8; 16]) -> Result<(), Error> {
+        match self {
+            Aead::ChaCha20Poly1305(aead) => aead.decrypt(nonce, aad, buffer, tag),
+            Aead::DChaCha20Aead(aead) => aead.decrypt(nonce, aad, buffer, tag)
+        }
+    }
+}
+
+/// Nonce of chunk `index`
+fn chunk_nonce(header: &[u8], index: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..7].copy_from_slice(&header[30..HEADER_LEN]);
+    nonce[7..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+/// Common part of the header, or `Error::InvalidLength` if `chunk_size` is
+/// 0 or above `MAX_CHUNK_SIZE`
+fn header(version: u8, algorithm: Algorithm, nonce: &[u8; NONCE_LEN], chunk_size: u32) -> io::Result<Vec<u8>> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(io::Error::other(Error::InvalidLength));
+    }
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(&MAGIC);
+    header.push(version);
+    header.push(algorithm as u8);
+    header.extend_from_slice(&chunk_size.to_le_bytes());
+    header.extend_from_slice(nonce);
+    Ok(header)
+}
+
+/// Reads and checks the common part of the header, returns it along with the
+/// algorithm and chunk size it holds
+fn read_header(inner: &mut impl Read) -> io::Result<(Vec<u8>, Algorithm, u32)> {
+    let mut header = vec![0u8; HEADER_LEN];
+    if read_full(inner, &mut header)? < HEADER_LEN {
+        return Err(io::Error::other(Error::Truncated));
+    }
+
+    let chunk_size = u32::from_le_bytes(header[10..14].try_into().unwrap());
+    let algorithm = Algorithm::from_u8(header[9]);
+    match algorithm {
+        Some(algorithm) if header[..8] == MAGIC
+            && (header[8] == VERSION || header[8] == PASSWORD_VERSION)
+            && chunk_size > 0
+            && chunk_size <= MAX_CHUNK_SIZE => Ok((header, algorithm, chunk_size)),
+        _ => Err(io::Error::other(Error::InvalidContainer))
+    }
+}
+
+/// Reads until `buf` is full or EOF, returns the bytes read
+fn read_full(inner: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match inner.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(filled)
+}
+
+/// Writes a container into `W`.
+///
+/// `finish` must be called to write the final chunk, without it the container
+/// reads as truncated. `nonce` should be random, it must never be used twice
+/// with the same key.
+///
+/// Once a chunk failed to be written, every later `write` and `finish` fails:
+/// the container is cut short, and sealing the chunk again would reuse its nonce
+pub struct ContainerWriter<W: Write> {
+    inner: W,
+    aead: Aead,
+    header: Vec<u8>,
+    chunk_size: usize,
+    /// Plaintext of the chunk being filled
+    buffer: Zeroizing<Vec<u8>>,
+    /// Index of the chunk being filled
+    index: u32,
+    /// Set once a chunk failed to be written
+    failed: bool
+}
+
+impl<W: Write> ContainerWriter<W> {
+    /// Writes the header and returns the writer.
+    /// Fails with `Error::InvalidLength` if `chunk_size` is 0 or above `MAX_CHUNK_SIZE`
+    pub fn new(inner: W, key: &[u8; 32], algorithm: Algorithm, nonce: &[u8; NONCE_LEN], chunk_size: u32) -> io::Result<Self> {
+        let header = header(VERSION, algorithm, nonce, chunk_size)?;
+        Self::start(inner, key, algorithm, header, chunk_size)
+    }
+
+    /// Same as `new` with the key derived from `password` by `kdf`. `salt`
+    /// should be random, it is stored in the header along with `kdf`.
+    /// Fails with `Error::WeakKdf` if `kdf` is below its minimum parameters,
+    /// `Error::InvalidKdf` if it is above its maximum ones
+    #[cfg(feature = "password")]
+    pub fn with_password(
+        inner: W, password: &[u8], kdf: Kdf, salt: &[u8; SALT_LEN], algorithm: Algorithm, nonce: &[u8; NONCE_LEN], chunk_size: u32
+    ) -> io::Result<Self> {
+        let mut header = header(PASSWORD_VERSION, algorithm, nonce, chunk_size)?;
+        header.extend_from_slice(&kdf.to_bytes());
+        header.extend_from_slice(salt);
+        let key = kdf.derive(password, salt).map_err(io::Error::other)?;
+        Self::start(inner, &key, algorithm, header, chunk_size)
+    }
+
+    fn start(mut inner: W, key: &[u8; 32], algorithm: Algorithm, header: Vec<u8>, chunk_size: u32) -> io::Result<Self> {
+        inner.write_all(&header)?;
+        Ok(Self {
+            inner,
+            aead: Aead::new(algorithm, key, &header),
+            header,
+            chunk_size: chunk_size as usize,
+            buffer: Zeroizing::new(Vec::with_capacity(chunk_size as usize)),
+            index: 0,
+            failed: false
+        })
+    }
+
+    /// Seals the buffered plaintext as a chunk and writes it out. Any error
+    /// marks the writer as failed
+    fn write_chunk(&mut self, last: bool) -> io::Result<()> {
+        if self.failed {
+            return Err(io::Error::other("container writer failed earlier, the container is incomplete"));
+        }
+        self.failed = true;
+
+        let next = self.index.checked_add(1).ok_or_else(|| io::Error::other(Error::LimitExceeded))?;
+        let nonce = chunk_nonce(&self.header, self.index, last);
+        let tag = self.aead.encrypt(&nonce, &self.header, &mut self.buffer);
+        self.inner.write_all(&[last as u8])?;
+        self.inner.write_all(&self.buffer)?;
+        self.inner.write_all(&tag)?;
+        self.buffer.clear();
+        self.index  = next;
+        self.failed = false;
+        Ok(())
+    }
+
+    /// Writes the final chunk and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ContainerWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() == self.chunk_size {
+            // a full buffer is only left behind by a failed chunk
+            self.write_chunk(false)?;
+        }
+        let n = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == self.chunk_size {
+            self.write_chunk(false)?;
+        }
+        Ok(n)
+    }
+
+    /// Flushes the inner writer. The chunk being filled is only written once
+    /// full or by `finish`
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the plaintext of a container from `R`, a chunk at a time.
+///
+/// Nothing of a chunk is returned before its tag is checked. Errors carry an
+/// `Error`: `TagMismatch` for a chunk altered, moved, or sealed with another
+/// key or algorithm, `Truncated` if the container ends before its final chunk,
+/// `InvalidContainer` for a malformed header or data past the largest final chunk.
+///
+/// Once a chunk failed to read or to authenticate, every later `read` fails
+/// with the same error: an inner error cutting a chunk short leaves the
+/// reader with `Truncated`
+pub struct ContainerReader<R: Read> {
+    inner: R,
+    aead: Aead,
+    header: Vec<u8>,
+    algorithm: Algorithm,
+    chunk_size: usize,
+    /// Plaintext of the last chunk read, from `pos` on
+    buffer: Zeroizing<Vec<u8>>,
+    pos: usize,
+    /// Index of the next chunk
+    index: u32,
+    /// Set once the final chunk was read
+    done: bool,
+    /// Set once a chunk failed, the reader is out of step with the chunks
+    failed: Option<Error>
+}
+
+impl<R: Read> ContainerReader<R> {
+    /// Reads and checks the header. It is only authenticated along with the
+    /// first chunk.
+    /// Fails with `Error::PasswordRequired` if the container was written with
+    /// a password
+    pub fn new(mut inner: R, key: &[u8; 32]) -> io::Result<Self> {
+        let (header, algorithm, chunk_size) = read_header(&mut inner)?;
+        if header[8] != VERSION {
+            return Err(io::Error::other(Error::PasswordRequired));
+        }
+        Ok(Self::start(inner, key, header, algorithm, chunk_size))
+    }
+
+    /// Same as `new` for a container written with a password. Fails with
+    /// `Error::KeyRequired` if it was written with a key, or `Error::WeakKdf`
+    /// if its key derivation parameters are below the minimum, before
+    /// spending any time on them. Parameters above the maximum make for an
+    /// `Error::InvalidContainer`
+    #[cfg(feature = "password")]
+    pub fn with_password(mut inner: R, password: &[u8]) -> io::Result<Self> {
+        let (mut header, algorithm, chunk_size) = read_header(&mut inner)?;
+        if header[8] != PASSWORD_VERSION {
+            return Err(io::Error::other(Error::KeyRequired));
+        }
+
+        header.resize(PASSWORD_HEADER_LEN, 0);
+        if read_full(&mut inner, &mut header[HEADER_LEN..])? < PASSWORD_HEADER_LEN - HEADER_LEN {
+            return Err(io::Error::other(Error::Truncated));
+        }
+        let kdf = Kdf::from_bytes(header[HEADER_LEN..HEADER_LEN + 13].try_into().unwrap())
+            .ok_or_else(|| io::Error::other(Error::InvalidContainer))?;
+        // parameters out of bounds are a malformed header here
+        let key = kdf.derive(password, header[HEADER_LEN + 13..].try_into().unwrap()).map_err(|e| match e {
+            Error::InvalidKdf => io::Error::other(Error::InvalidContainer),
+            e => io::Error::other(e)
+        })?;
+        Ok(Self::start(inner, &key, header, algorithm, chunk_size))
+    }
+
+    fn start(inner: R, key: &[u8; 32], header: Vec<u8>, algorithm: Algorithm, chunk_size: u32) -> Self {
+        Self {
+            inner,
+            aead: Aead::new(algorithm, key, &header),
+            header,
+            algorithm,
+            chunk_size: chunk_size as usize,
+            buffer: Zeroizing::new(Vec::with_capacity(chunk_size as usize + 16)),
+            pos: 0,
+            index: 0,
+            done: false,
+            failed: None
+        }
+    }
+
+    /// Algorithm named in the header
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Chunk size named in the header
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size as u32
+    }
+
+    /// Reads, checks and decrypts the next chunk into `buffer`. Past its
+    /// flag, any error marks the reader as failed
+    fn read_chunk(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.pos = 0;
+
+        let mut flag = [0u8; 1];
+        if read_full(&mut self.inner, &mut flag)? == 0 {
+            return Err(io::Error::other(Error::Truncated));
+        }
+
+        let result = self.open_chunk(flag[0]);
+        if let Err(e) = &result {
+            self.buffer.clear();
+            let error = e.get_ref().and_then(|e| e.downcast_ref::<Error>());
+            self.failed = Some(error.copied().unwrap_or(Error::Truncated));
+        }
+        result
+    }
+
+    /// Reads the rest of the chunk flagged by `flag`, checks and decrypts it
+    fn open_chunk(&mut self, flag: u8) -> io::Result<()> {
+        let truncated = || io::Error::other(Error::Truncated);
+        let last = match flag {
+            0 => false,
+            1 => true,
+            _ => return Err(io::Error::other(Error::InvalidContainer))
+        };
+
+        self.buffer.resize(self.chunk_size + 16, 0);
+        let n = read_full(&mut self.inner, &mut self.buffer)?;
+        if n < 16 || (!last && n < self.chunk_size + 16) {
+            return Err(truncated());
+        }
+        // nothing may follow the final chunk
+        if last && read_full(&mut self.inner, &mut [0u8; 1])? > 0 {
+            return Err(io::Error::other(Error::InvalidContainer));
+        }
+
+        self.buffer.truncate(n);
+        let tag: [u8; 16] = self.buffer[n - 16..].try_into().unwrap();
+        self.buffer.truncate(n - 16);
+        let nonce = chunk_nonce(&self.header, self.index, last);
+        self.aead.decrypt(&nonce, &self.header, &mut self.buffer, &tag).map_err(io::Error::other)?;
+
+        self.done  = last;
+        self.index = self.index.checked_add(1).ok_or_else(|| io::Error::other(Error::LimitExceeded))?;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ContainerReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(error) = self.failed {
+            return Err(io::Error::other(error));
+        }
+        while self.pos == self.buffer.len() && !self.done {
+            self.read_chunk()?;
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use rand::RngCore;
+
+    use crate::Error;
+    use super::{Algorithm, ContainerReader, ContainerWriter, HEADER_LEN, NONCE_LEN};
+
+    const KEY: [u8; 32] = [7u8; 32];
+    const CHUNK: usize = 100;
+
+    fn seal(algorithm: Algorithm, msg: &[u8]) -> Vec<u8> {
+        let mut writer = ContainerWriter::new(Vec::new(), &KEY, algorithm, &[3u8; NONCE_LEN], CHUNK as u32).unwrap();
+        // odd sized writes, to cross chunk boundaries
+        for part in msg.chunks(37) {
+            writer.write_all(part).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn open(container: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut reader = ContainerReader::new(container, &KEY).map_err(error)?;
+        let mut msg = Vec::new();
+        reader.read_to_end(&mut msg).map_err(error)?;
+        Ok(msg)
+    }
+
+    fn error(e: std::io::Error) -> Error {
+        *e.into_inner().unwrap().downcast::<Error>().unwrap()
+    }
+
+    /// Offset of chunk `index`, all chunks before it being full
+    fn chunk_offset(index: usize) -> usize {
+        HEADER_LEN + index * (1 + CHUNK + 16)
+    }
+
+    #[test]
+    fn round_trip() {
+        for algorithm in [Algorithm::ChaCha20Poly1305, Algorithm::DChaCha20Aead] {
+            for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 10 * CHUNK, 10_000] {
+                let mut msg = vec![0u8; len];
+                rand::rngs::OsRng.fill_bytes(&mut msg);
+                let container = seal(algorithm, &msg);
+                assert_eq!(container.len(), HEADER_LEN + (len / CHUNK + 1) * 17 + len);
+
+                let reader = ContainerReader::new(container.as_slice(), &KEY).unwrap();
+                assert_eq!(reader.algorithm(), algorithm);
+                assert_eq!(reader.chunk_size(), CHUNK as u32);
+                assert_eq!(open(&container).unwrap(), msg);
+            }
+        }
+    }
+
+    #[test]
+    fn truncation() {
+        let msg = vec![5u8; 3 * CHUNK + 10];
+        let container = seal(Algorithm::DChaCha20Aead, &msg);
+
+        // dropping the final chunk, or everything from any byte on
+        assert_eq!(open(&container[..chunk_offset(3)]), Err(Error::Truncated));
+        for len in [0, 5, HEADER_LEN, HEADER_LEN + 1, chunk_offset(1) + 50, container.len() - 17] {
+            assert_eq!(open(&container[..len]), Err(Error::Truncated), "{len}");
+        }
+        // cutting into the final chunk leaves a shorter one with the wrong tag
+        assert_eq!(open(&container[..container.len() - 1]), Err(Error::TagMismatch));
+
+        // marking an earlier chunk as final
+        let mut forged = container[..chunk_offset(2)].to_vec();
+        forged[chunk_offset(1)] = 1;
+        assert_eq!(open(&forged), Err(Error::TagMismatch));
+
+        // the final chunk runs up to the end, what follows it is taken as part of it
+        let mut trailing = container.clone();
+        trailing.push(0);
+        assert_eq!(open(&trailing), Err(Error::TagMismatch));
+        let mut trailing = seal(Algorithm::DChaCha20Aead, &msg[..3 * CHUNK]);
+        let full = trailing.len();
+        trailing.resize(full + 1 + CHUNK + 16, 0);
+        assert_eq!(open(&trailing[..full]).unwrap(), msg[..3 * CHUNK]);
+        assert_eq!(open(&trailing), Err(Error::InvalidContainer));
+    }
+
+    /// Reader failing once with `TimedOut` when reaching byte `at`
+    struct Flaky<'a> {
+        data: &'a [u8],
+        pos: usize,
+        at: Option<usize>
+    }
+
+    impl Read for Flaky<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let mut end = self.data.len();
+            if let Some(at) = self.at {
+                if self.pos == at {
+                    self.at = None;
+                    return Err(std::io::ErrorKind::TimedOut.into());
+                }
+                end = end.min(at.max(self.pos));
+            }
+            let n = buf.len().min(end - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn retry_after_error() {
+        let msg = vec![5u8; 3 * CHUNK + 10];
+        let container = seal(Algorithm::DChaCha20Aead, &msg);
+        let mut buf = [0u8; 4 * CHUNK];
+
+        // an inner error inside a chunk leaves it unreadable for good
+        let flaky = Flaky { data: &container, pos: 0, at: Some(chunk_offset(1) + 40) };
+        let mut reader = ContainerReader::new(flaky, &KEY).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), CHUNK);
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        for _ in 0..2 {
+            assert_eq!(error(reader.read(&mut buf).unwrap_err()), Error::Truncated);
+        }
+
+        // between chunks, nothing was consumed and reading goes on
+        let flaky = Flaky { data: &container, pos: 0, at: Some(chunk_offset(1)) };
+        let mut reader = ContainerReader::new(flaky, &KEY).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), CHUNK);
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        let mut opened = buf[..CHUNK].to_vec();
+        reader.read_to_end(&mut opened).unwrap();
+        assert_eq!(opened, msg);
+
+        // no plaintext, nor ciphertext, after a failed tag or a truncation
+        let mut flipped = container.clone();
+        flipped[chunk_offset(1) + 10] ^= 1;
+        let len = chunk_offset(2) + 50;
+        for (data, expected) in [(&flipped[..], Error::TagMismatch), (&container[..len], Error::Truncated)] {
+            let mut reader = ContainerReader::new(data, &KEY).unwrap();
+            let mut opened = Vec::new();
+            assert_eq!(error(reader.read_to_end(&mut opened).unwrap_err()), expected);
+            for _ in 0..2 {
+                assert_eq!(error(reader.read(&mut buf).unwrap_err()), expected);
+            }
+            assert!(opened.len() % CHUNK == 0 && opened.iter().all(|b| *b == 5));
+        }
+    }
+
+    /// Writer failing once with `TimedOut` on its `fail_at`th call
+    struct FlakyWriter {
+        data: Vec<u8>,
+        calls: usize,
+        fail_at: usize
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            if self.calls == self.fail_at {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_retry_after_error() {
+        let msg = [0x5au8; 3 * CHUNK];
+        // each chunk takes three calls after the header: flag, ciphertext, tag
+        for fail_at in 2..=7 {
+            let inner = FlakyWriter { data: Vec::new(), calls: 0, fail_at };
+            let mut writer = ContainerWriter::new(inner, &KEY, Algorithm::ChaCha20Poly1305, &[3u8; NONCE_LEN], CHUNK as u32).unwrap();
+
+            let mut written = 0;
+            let mut failed = false;
+            for _ in 0..10 {
+                match writer.write(&msg[written..]) {
+                    Ok(n) => written += n,
+                    Err(_) => failed = true
+                }
+                if written == msg.len() {
+                    break;
+                }
+            }
+            assert!(failed, "{fail_at}");
+            assert!(written < msg.len(), "{fail_at}");
+            assert!(writer.flush().is_ok());
+
+            // no plaintext, and no chunk sealed twice, made it through
+            let data = std::mem::take(&mut writer.inner.data);
+            assert!(writer.finish().is_err(), "{fail_at}");
+            assert!(!data.windows(16).any(|w| w == &msg[..16]), "{fail_at}");
+            assert!(data.len() <= chunk_offset(2), "{fail_at}");
+        }
+    }
+
+    #[test]
+    fn reordering() {
+        let mut msg = vec![0u8; 3 * CHUNK + 10];
+        rand::rngs::OsRng.fill_bytes(&mut msg);
+        let container = seal(Algorithm::ChaCha20Poly1305, &msg);
+
+        let mut swapped = container.clone();
+        let (first, second) = (chunk_offset(0)..chunk_offset(1), chunk_offset(1)..chunk_offset(2));
+        swapped[first.clone()].copy_from_slice(&container[second.clone()]);
+        swapped[second].copy_from_slice(&container[first]);
+        assert_eq!(open(&swapped), Err(Error::TagMismatch));
+
+        let mut dropped = container[..chunk_offset(1)].to_vec();
+        dropped.extend_from_slice(&container[chunk_offset(2)..]);
+        assert_eq!(open(&dropped), Err(Error::TagMismatch));
+
+        let mut flipped = container.clone();
+        flipped[chunk_offset(2) + 10] ^= 1;
+        assert_eq!(open(&flipped), Err(Error::TagMismatch));
+    }
+
+    #[test]
+    fn algorithm_confusion() {
+        let msg = vec![5u8; 2 * CHUNK];
+        for (algorithm, other) in [(Algorithm::ChaCha20Poly1305, 2), (Algorithm::DChaCha20Aead, 1)] {
+            let mut container = seal(algorithm, &msg);
+            container[9] = other;
+            assert_eq!(open(&container), Err(Error::TagMismatch));
+        }
+
+        let container = seal(Algorithm::ChaCha20Poly1305, &msg);
+        // magic, version, algorithm, chunk size above the limit
+        for (pos, value) in [(0, b'X'), (8, 3), (9, 3), (13, 0x10)] {
+            let mut bad = container.clone();
+            bad[pos] = value;
+            assert_eq!(open(&bad), Err(Error::InvalidContainer), "byte {pos}");
+        }
+
+        // subkey salt, chunk nonce prefix
+        for pos in [14, 30] {
+            let mut bad = container.clone();
+            bad[pos] ^= 1;
+            assert_eq!(open(&bad), Err(Error::TagMismatch), "byte {pos}");
+        }
+    }
+
+    #[test]
+    fn subkey() {
+        // nonces sharing their chunk nonce prefix still seal under different subkeys
+        let msg = [0u8; CHUNK];
+        let mut nonce = [3u8; NONCE_LEN];
+        let mut sealed = Vec::new();
+        for salt in [0, 1] {
+            nonce[0] = salt;
+            let mut writer = ContainerWriter::new(Vec::new(), &KEY, Algorithm::ChaCha20Poly1305, &nonce, CHUNK as u32).unwrap();
+            writer.write_all(&msg).unwrap();
+            sealed.push(writer.finish().unwrap());
+        }
+        assert_ne!(sealed[0][HEADER_LEN..], sealed[1][HEADER_LEN..]);
+
+        // the chunks are sealed with HChaCha20(key, salt), not the key
+        let subkey = crate::hchacha20(&KEY, &nonce[..16].try_into().unwrap());
+        let mut chunk_nonce = [0u8; 12];
+        chunk_nonce[..7].copy_from_slice(&nonce[16..]);
+        let mut expected = msg;
+        let tag = crate::ChaCha20Poly1305::new(&subkey).encrypt(&chunk_nonce, &sealed[1][..HEADER_LEN], &mut expected);
+        assert_eq!(sealed[1][HEADER_LEN + 1..HEADER_LEN + 1 + CHUNK], expected);
+        assert_eq!(sealed[1][HEADER_LEN + 1 + CHUNK..HEADER_LEN + 17 + CHUNK], tag);
+    }
+
+    #[test]
+    #[cfg(feature = "password")]
+    fn password() {
+        use crate::Kdf;
+
+        let msg = vec![5u8; 3 * CHUNK + 10];
+        let argon2id = Kdf::default().min();
+        for kdf in [argon2id, Kdf::SCRYPT.min()] {
+            let mut writer = ContainerWriter::with_password(Vec::new(), b"hunter2", kdf, &[1u8; 16], Algorithm::DChaCha20Aead, &[3u8; NONCE_LEN], CHUNK as u32).unwrap();
+            writer.write_all(&msg).unwrap();
+            let container = writer.finish().unwrap();
+
+            let mut opened = Vec::new();
+            ContainerReader::with_password(container.as_slice(), b"hunter2").unwrap().read_to_end(&mut opened).unwrap();
+            assert_eq!(opened, msg);
+
+            let mut reader = ContainerReader::with_password(container.as_slice(), b"hunter3").unwrap();
+            assert_eq!(error(reader.read_to_end(&mut Vec::new()).unwrap_err()), Error::TagMismatch);
+            assert_eq!(open(&container), Err(Error::PasswordRequired));
+        }
+
+        let container = seal(Algorithm::DChaCha20Aead, &msg);
+        assert_eq!(error(ContainerReader::with_password(container.as_slice(), b"hunter2").err().unwrap()), Error::KeyRequired);
+
+        let weak = Kdf::Argon2id { m_cost: 1024, t_cost: 1, p_cost: 1 };
+        let err = ContainerWriter::with_password(Vec::new(), b"hunter2", weak, &[1u8; 16], Algorithm::DChaCha20Aead, &[3u8; NONCE_LEN], CHUNK as u32).err().unwrap();
+        assert_eq!(error(err), Error::WeakKdf);
+        let costly = Kdf::Argon2id { m_cost: 2 * 1024 * 1024, t_cost: 3, p_cost: 1 };
+        let err = ContainerWriter::with_password(Vec::new(), b"hunter2", costly, &[1u8; 16], Algorithm::DChaCha20Aead, &[3u8; NONCE_LEN], CHUNK as u32).err().unwrap();
+        assert_eq!(error(err), Error::InvalidKdf);
+
+        // a header forged down to cheap parameters is refused before deriving anything
+        let mut writer = ContainerWriter::with_password(Vec::new(), b"hunter2", argon2id, &[1u8; 16], Algorithm::ChaCha20Poly1305, &[3u8; NONCE_LEN], CHUNK as u32).unwrap();
+        writer.write_all(&msg).unwrap();
+        let mut container = writer.finish().unwrap();
+        container[HEADER_LEN + 1..HEADER_LEN + 5].copy_from_slice(&1024u32.to_le_bytes());
+        assert_eq!(error(ContainerReader::with_password(container.as_slice(), b"hunter2").err().unwrap()), Error::WeakKdf);
+        container[HEADER_LEN + 1..HEADER_LEN + 5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(error(ContainerReader::with_password(container.as_slice(), b"hunter2").err().unwrap()), Error::InvalidContainer);
+        container[HEADER_LEN] = 9;
+        assert_eq!(error(ContainerReader::with_password(container.as_slice(), b"hunter2").err().unwrap()), Error::InvalidContainer);
+        assert_eq!(error(ContainerReader::with_password(&container[..HEADER_LEN + 10], b"hunter2").err().unwrap()), Error::Truncated);
+    }
+}
@@ -1,9 +1,40 @@
-use core::slice::SlicePattern;
-use std::{ops::AddAssign, simd::{u32x16, u32x4, u32x8, u8x64, ToBytes}};
+use core::{mem::MaybeUninit, ops::AddAssign};
 
-use zeroize::Zeroizing;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-pub struct DChaCha20 {
+use crate::{backend::Backend, chacha20::{copy_to_uninit, xchacha20_subkey, ChaCha}, error::Error, simd::{u32x16, u32x4, u32x8, u8x64, ToBytes}, state::{CipherState, Kind, Snapshot}};
+
+/// DChaCha reduced to 8 rounds
+pub type DChaCha8 = DChaCha<8>;
+/// DChaCha reduced to 12 rounds
+pub type DChaCha12 = DChaCha<12>;
+/// DChaCha with the full 20 rounds of ChaCha20
+pub type DChaCha20 = DChaCha<20>;
+/// DChaCha20 on the original ChaCha layout, 64 bit counter and 64 bit nonce
+pub type DChaCha20Legacy = DChaCha<20, true>;
+
+/// Whole blocks are processed this many bytes at a time, one pass for the
+/// keystream and one for the digest
+const SEGMENT_LEN: usize = 64 * 64;
+
+/// Bytes of whole blocks each thread of `decrypt_parallel` goes through
+#[cfg(feature = "rayon")]
+const PARALLEL_SEGMENT_LEN: usize = 64 * 1024;
+
+/// What comes before the blocks given to `DChaCha::decrypt_at`
+#[derive(Debug, Clone, Copy)]
+pub enum Preceding<'a> {
+    /// Ciphertext of the message up to the first block, whole blocks only
+    Ciphertext(&'a [u8]),
+    /// Digest of that ciphertext, as returned by `DChaCha::digest`
+    Digest(&'a [u8; 64])
+}
+
+/// DChaCha stream cipher with `R` rounds, see the `DChaCha8`, `DChaCha12`
+/// and `DChaCha20` aliases.
+/// With `LEGACY` the state follows the original ChaCha layout, where the
+/// block counter carries from word 12 into word 13 and the nonce is 64 bit
+pub struct DChaCha<const R: usize, const LEGACY: bool = false> {
     /// This is where the initial state is stored
     state: u32x16,
     /// Calculated keystream
@@ -12,13 +43,40 @@ pub struct DChaCha20 {
     keystream_buffer: u8x64,
     /// Digest for XORing previous ciphertext generated random bytes
     prev_dig: u8x64,
-    /// Temp storage for previous ciphertext
-    prev_ciph: Zeroizing<[u8; 64]>,
-    xorshift: XorShiftSIMD
+    /// Ciphertext of the block currently being filled by the streaming API
+    partial: Zeroizing<[u8; 64]>,
+    /// Bytes of `partial` filled so far, 0 when on a block boundary
+    offset: usize,
+    /// Set once the block counter went past its last value
+    exhausted: bool,
+    xorshift: XorShiftSIMD,
+    /// Code generating the keystream of large buffers
+    backend: Backend
 }
 
-impl DChaCha20 {
+impl<const R: usize> DChaCha<R> {
     pub fn new(key: &[u8; 32], nonce: &[u8; 12]) -> Self {
+        Self::with_state(key, [
+            0, u32_from_le_bytes(&nonce[..4]), u32_from_le_bytes(&nonce[4..8]), u32_from_le_bytes(&nonce[8..12]),
+        ])
+    }
+}
+
+impl<const R: usize> DChaCha<R, true> {
+    pub fn new(key: &[u8; 32], nonce: &[u8; 8]) -> Self {
+        Self::with_state(key, [
+            0, 0, u32_from_le_bytes(&nonce[..4]), u32_from_le_bytes(&nonce[4..8]),
+        ])
+    }
+}
+
+impl<const R: usize, const LEGACY: bool> DChaCha<R, LEGACY> {
+    /// Number of blocks the counter can address
+    const BLOCKS: u128 = if LEGACY { 1 << 64 } else { 1 << 32 };
+
+    /// `last_row` holds the counter and nonce words, laid out as the variant expects
+    fn with_state(key: &[u8; 32], last_row: [u32; 4]) -> Self {
+        const { assert!(R > 0 && R.is_multiple_of(2), "DChaCha round count must be even") };
         Self {
             state: u32x16::from_array([
                 /*
@@ -27,6 +85,7 @@ impl DChaCha20 {
                 kkkkkkkk kkkkkkkk kkkkkkkk kkkkkkkk
                 bbbbbbbb nnnnnnnn nnnnnnnn nnnnnnnn
                 c=constant k=key b=blockcount n=nonce
+                (legacy: bbbbbbbb bbbbbbbb nnnnnnnn nnnnnnnn)
                 */
 
                 // The 4 u32 constants of chacha
@@ -35,66 +94,78 @@ impl DChaCha20 {
                 u32_from_le_bytes(&key[0..4]), u32_from_le_bytes(&key[4..8]), u32_from_le_bytes(&key[8..12]), u32_from_le_bytes(&key[12..16]),
                 u32_from_le_bytes(&key[16..20]), u32_from_le_bytes(&key[20..24]), u32_from_le_bytes(&key[24..28]), u32_from_le_bytes(&key[28..32]),
                 // Bit counter + nonce
-                0, u32_from_le_bytes(&nonce[..4]), u32_from_le_bytes(&nonce[4..8]), u32_from_le_bytes(&nonce[8..12]),
+                last_row[0], last_row[1], last_row[2], last_row[3],
             ]),
             keystream: u32x16::from_array([0u32; 16]),
             keystream_buffer: u8x64::from_array([0u8; 64]),
             prev_dig: u8x64::from_array([0u8; 64]),
-            prev_ciph: Zeroizing::new([0u8; 64]),
-            xorshift: XorShiftSIMD::new()
+            partial: Zeroizing::new([0u8; 64]),
+            offset: 0,
+            exhausted: false,
+            xorshift: XorShiftSIMD::new(),
+            backend: Backend::detect()
         }
     }
 
-    #[inline(always)]
-    fn quarter_round(a: &mut u32x4, b: &mut u32x4, c: &mut u32x4, d: &mut u32x4) {
-        a.add_assign(*b);
-        *d ^= *a;
-        *d  = (*d << 16) | (*d >> 16);
-        c.add_assign(*d);
-        *b ^= *c;
-        *b  = (*b << 12) | (*b >> 20);
-        a.add_assign(*b);
-        *d ^= *a;
-        *d  = (*d << 8) | (*d >> 24);
-        c.add_assign(*d);
-        *b ^= *c;
-        *b  = (*b << 7) | (*b >> 25);
+    /// Backend picked for this CPU when the cipher was created
+    pub fn backend(&self) -> Backend {
+        self.backend
     }
 
-    #[inline(always)]
-    fn rounds(state: &mut u32x16) {
-        let mut a = u32x4::from_slice(&state[0..4]);
-        let mut b = u32x4::from_slice(&state[4..8]);
-        let mut c = u32x4::from_slice(&state[8..12]);
-        let mut d = u32x4::from_slice(&state[12..16]);
-        for _ in 0..10 {
-            // column round
-            Self::quarter_round(&mut a, &mut b, &mut c, &mut d);
-
-            // diagonal round
-            b = u32x4::from_array([b[1], b[2], b[3], b[0]]);
-            c = u32x4::from_array([c[2], c[3], c[0], c[1]]);
-            d = u32x4::from_array([d[3], d[0], d[1], d[2]]);
-
-            Self::quarter_round(&mut a, &mut b, &mut c, &mut d);
-
-            b = u32x4::from_array([b[3], b[0], b[1], b[2]]);
-            c = u32x4::from_array([c[2], c[3], c[0], c[1]]);
-            d = u32x4::from_array([d[1], d[2], d[3], d[0]]);
-        }
-
-        a.copy_to_slice(&mut state[0..4]);
-        b.copy_to_slice(&mut state[4..8]);
-        c.copy_to_slice(&mut state[8..12]);
-        d.copy_to_slice(&mut state[12..16]);
+    /// Forces the keystream of large buffers through `backend`, mostly to
+    /// test one that is not the fastest
+    ///
+    /// # Panics
+    /// If the CPU does not support `backend`
+    pub fn set_backend(&mut self, backend: Backend) {
+        assert!(backend.is_supported(), "{backend:?} backend not supported by this CPU");
+        self.backend = backend;
     }
 
     #[inline(always)]
     fn block_fn(&mut self) {
         self.keystream = self.state;
-        Self::rounds(&mut self.keystream);
+        ChaCha::<R, LEGACY>::rounds(&mut self.keystream);
         self.keystream.add_assign(&self.state);
-        self.state[12] = self.state[12].wrapping_add(1);
+        self.increment_counter();
+    }
+
+    #[inline(always)]
+    fn increment_counter(&mut self) {
+        let (counter, overflow) = self.state[12].overflowing_add(1);
+        self.state[12] = counter;
+        if overflow {
+            if LEGACY {
+                let (high, overflow) = self.state[13].overflowing_add(1);
+                self.state[13] = high;
+                self.exhausted |= overflow;
+            } else {
+                self.exhausted = true;
+            }
+        }
+    }
+
+    /// Block the counter is on, `BLOCKS` once it went past its last value
+    fn next_block(&self) -> u128 {
+        if self.exhausted {
+            Self::BLOCKS
+        } else if LEGACY {
+            (self.state[13] as u128) << 32 | self.state[12] as u128
+        } else {
+            self.state[12] as u128
+        }
+    }
+
+    /// Bytes of keystream left before the block counter runs out,
+    /// saturating at `u64::MAX`
+    pub fn remaining(&self) -> u64 {
+        let next_block = self.next_block();
+        let pos = match self.offset {
+            0 => next_block * 64,
+            // the block being filled is the one before the counter
+            n => (next_block - 1) * 64 + n as u128
+        };
+        u64::try_from(Self::BLOCKS * 64 - pos).unwrap_or(u64::MAX)
     }
 
     #[inline(always)]
@@ -118,32 +189,505 @@ impl DChaCha20 {
         }
     }
 
+    /// XORs the plain keystream, without the digest, into `buff` made of
+    /// whole blocks. The keystream only depends on the counter, so the
+    /// backend can generate several blocks at once
+    fn apply_keystream_blocks(&mut self, buff: &mut [u8]) {
+        let blocks = self.backend.apply_keystream::<R, LEGACY>(self.state.to_array(), buff);
+        for _ in 0..blocks {
+            self.increment_counter();
+        }
+
+        for chunk in buff[blocks * 64..].chunks_exact_mut(64) {
+            self.block_fn();
+            self.convert_keystream_to_u8_arr();
+            let mut b = u8x64::from_slice(chunk);
+            b ^= self.keystream_buffer;
+            b.copy_to_slice(chunk);
+        }
+    }
+
+    /// Continues the block a previous streaming call stopped in the middle of
+    /// and returns what is left of `buff`
+    fn continue_block<'a>(&mut self, buff: &'a mut [u8], encrypting: bool) -> &'a mut [u8] {
+        if self.offset == 0 || buff.is_empty() {
+            return buff;
+        }
+
+        let (head, rest) = buff.split_at_mut((64 - self.offset).min(buff.len()));
+        for (i, byte) in head.iter_mut().enumerate() {
+            let pos = self.offset + i;
+            if !encrypting {
+                self.partial[pos] = *byte;
+            }
+            *byte ^= self.keystream_buffer[pos] ^ self.prev_dig[pos];
+            if encrypting {
+                self.partial[pos] = *byte;
+            }
+        }
+
+        self.offset += head.len();
+        if self.offset == 64 {
+            self.prev_dig ^= u8x64::from_array(*self.partial);
+            self.offset = 0;
+        }
+        rest
+    }
+
+    /// Encrypts part of a message. The ciphertext only depends on the position
+    /// in the stream, not on how the message is split across calls.
+    /// `finish` must be called once the whole message went through
+    ///
+    /// # Panics
+    /// If the keystream runs out, see `try_encrypt_stream`
+    pub fn encrypt_stream(&mut self, plaintext: &mut [u8]) {
+        self.try_encrypt_stream(plaintext).expect("DChaCha20 keystream exhausted");
+    }
+
+    /// Fallible `encrypt_stream`, returns `Error::LimitExceeded` without
+    /// touching `plaintext` if the keystream left is too short
+    pub fn try_encrypt_stream(&mut self, plaintext: &mut [u8]) -> Result<(), Error> {
+        if plaintext.len() as u64 > self.remaining() {
+            return Err(Error::LimitExceeded);
+        }
+
+        let buff = self.continue_block(plaintext, true);
+
+        // keystream first, then the digest which needs the ciphertext,
+        // a segment at a time so the second pass stays in cache
+        let (blocks, tail) = buff.split_at_mut(buff.len() / 64 * 64);
+        for segment in blocks.chunks_mut(SEGMENT_LEN) {
+            self.apply_keystream_blocks(segment);
+            for chunk in segment.chunks_exact_mut(64) {
+                let b = u8x64::from_slice(chunk) ^ self.prev_dig;
+                b.copy_to_slice(chunk);
+                self.prev_dig ^= b;
+            }
+        }
+
+        if !tail.is_empty() {
+            self.apply_keystream(tail);
+            self.partial[..tail.len()].copy_from_slice(tail);
+            self.offset = tail.len();
+        }
+        Ok(())
+    }
+
+    /// Decrypts part of a message produced by `encrypt_stream` or `encrypt`,
+    /// regardless of how it is split across calls.
+    /// `finish` must be called once the whole message went through
+    ///
+    /// # Panics
+    /// If the keystream runs out, see `try_decrypt_stream`
+    pub fn decrypt_stream(&mut self, ciphertext: &mut [u8]) {
+        self.try_decrypt_stream(ciphertext).expect("DChaCha20 keystream exhausted");
+    }
+
+    /// Fallible `decrypt_stream`, returns `Error::LimitExceeded` without
+    /// touching `ciphertext` if the keystream left is too short
+    pub fn try_decrypt_stream(&mut self, ciphertext: &mut [u8]) -> Result<(), Error> {
+        if ciphertext.len() as u64 > self.remaining() {
+            return Err(Error::LimitExceeded);
+        }
+
+        let buff = self.continue_block(ciphertext, false);
+
+        // digest first, while the ciphertext is still there, then the keystream
+        let (blocks, tail) = buff.split_at_mut(buff.len() / 64 * 64);
+        for segment in blocks.chunks_mut(SEGMENT_LEN) {
+            for chunk in segment.chunks_exact_mut(64) {
+                let c = u8x64::from_slice(chunk);
+                (c ^ self.prev_dig).copy_to_slice(chunk);
+                self.prev_dig ^= c;
+            }
+            self.apply_keystream_blocks(segment);
+        }
+
+        if !tail.is_empty() {
+            self.partial[..tail.len()].copy_from_slice(tail);
+            self.apply_keystream(tail);
+            self.offset = tail.len();
+        }
+        Ok(())
+    }
+
+    /// Ends the current message, folding its final partial block (if any)
+    /// into the digest
+    pub fn finish(&mut self) {
+        if self.offset > 0 {
+            self.xorshift.set_seed(&self.partial[..self.offset]);
+            self.xorshift.xor_with_slice(self.prev_dig.as_mut_array());
+            self.offset = 0;
+        }
+    }
+
+    /// Encrypts a whole message, same as `encrypt_stream` followed by `finish`
+    ///
+    /// # Panics
+    /// If the keystream runs out, see `try_encrypt`
     pub fn encrypt(&mut self, plaintext: &mut [u8]) {
-        for chunk in plaintext.chunks_mut(64) {
-            self.apply_keystream(chunk);
+        self.encrypt_stream(plaintext);
+        self.finish();
+    }
+
+    /// Decrypts a whole message, same as `decrypt_stream` followed by `finish`
+    ///
+    /// # Panics
+    /// If the keystream runs out, see `try_decrypt`
+    pub fn decrypt(&mut self, ciphertext: &mut [u8]) {
+        self.decrypt_stream(ciphertext);
+        self.finish();
+    }
+
+    /// Fallible `encrypt`, returns `Error::LimitExceeded` without
+    /// touching `plaintext` if the keystream left is too short
+    pub fn try_encrypt(&mut self, plaintext: &mut [u8]) -> Result<(), Error> {
+        self.try_encrypt_stream(plaintext)?;
+        self.finish();
+        Ok(())
+    }
+
+    /// Fallible `decrypt`, returns `Error::LimitExceeded` without
+    /// touching `ciphertext` if the keystream left is too short
+    pub fn try_decrypt(&mut self, ciphertext: &mut [u8]) -> Result<(), Error> {
+        self.try_decrypt_stream(ciphertext)?;
+        self.finish();
+        Ok(())
+    }
+
+    /// Encrypts the whole message `plaintext` into `ciphertext`, leaving
+    /// `plaintext` as is, same as `encrypt` otherwise.
+    /// Returns `Error::InvalidLength` if the buffers differ in length, or
+    /// `Error::LimitExceeded` if the keystream left is too short,
+    /// `ciphertext` is left untouched on error
+    pub fn encrypt_b2b(&mut self, plaintext: &[u8], ciphertext: &mut [u8]) -> Result<(), Error> {
+        self.check_b2b(plaintext, ciphertext.len())?;
+        ciphertext.copy_from_slice(plaintext);
+        self.try_encrypt(ciphertext)
+    }
+
+    /// Decrypts the whole message `ciphertext` into `plaintext`, same errors
+    /// as `encrypt_b2b`
+    pub fn decrypt_b2b(&mut self, ciphertext: &[u8], plaintext: &mut [u8]) -> Result<(), Error> {
+        self.check_b2b(ciphertext, plaintext.len())?;
+        plaintext.copy_from_slice(ciphertext);
+        self.try_decrypt(plaintext)
+    }
+
+    /// `encrypt_b2b` into a buffer that may not be initialized yet, returns
+    /// the ciphertext written to it
+    pub fn encrypt_b2b_uninit<'a>(&mut self, plaintext: &[u8], ciphertext: &'a mut [MaybeUninit<u8>]) -> Result<&'a mut [u8], Error> {
+        self.check_b2b(plaintext, ciphertext.len())?;
+        let ciphertext = copy_to_uninit(plaintext, ciphertext);
+        self.try_encrypt(ciphertext)?;
+        Ok(ciphertext)
+    }
+
+    /// `decrypt_b2b` into a buffer that may not be initialized yet, returns
+    /// the plaintext written to it
+    pub fn decrypt_b2b_uninit<'a>(&mut self, ciphertext: &[u8], plaintext: &'a mut [MaybeUninit<u8>]) -> Result<&'a mut [u8], Error> {
+        self.check_b2b(ciphertext, plaintext.len())?;
+        let plaintext = copy_to_uninit(ciphertext, plaintext);
+        self.try_decrypt(plaintext)?;
+        Ok(plaintext)
+    }
+
+    fn check_b2b(&self, src: &[u8], dst_len: usize) -> Result<(), Error> {
+        if src.len() != dst_len {
+            return Err(Error::InvalidLength);
+        }
+        if src.len() as u64 > self.remaining() {
+            return Err(Error::LimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Digest of the whole ciphertext blocks of the current message so far,
+    /// which together with the block counter is all a later block needs to
+    /// be decrypted, see `decrypt_at`
+    pub fn digest(&self) -> [u8; 64] {
+        self.prev_dig.to_array()
+    }
+
+    /// Replaces the digest, e.g. with one saved by `digest` for the block
+    /// the counter is on
+    pub fn set_digest(&mut self, digest: &[u8; 64]) {
+        self.prev_dig = u8x64::from_array(*digest);
+    }
+
+    /// Decrypts `ciphertext`, the part of a message starting at keystream
+    /// block `block`, without going through what comes before it.
+    /// `preceding` is either the ciphertext of the message up to that block
+    /// or its digest. The cipher is left as `decrypt_stream` would leave it,
+    /// so the rest of the message can follow.
+    ///
+    /// `block` counts from the start of the keystream, a message encrypted
+    /// right after `new` starts at block 0.
+    /// Returns `Error::InvalidLength` if the preceding ciphertext does not end
+    /// on a block boundary, or `Error::LimitExceeded` if `ciphertext` goes past
+    /// the end of the keystream. `ciphertext` is left untouched on error
+    pub fn decrypt_at(&mut self, ciphertext: &mut [u8], block: u64, preceding: Preceding<'_>) -> Result<(), Error> {
+        let prev_dig = match preceding {
+            Preceding::Ciphertext(preceding) if !preceding.len().is_multiple_of(64) => return Err(Error::InvalidLength),
+            Preceding::Ciphertext(preceding) => preceding
+                .chunks_exact(64)
+                .fold(u8x64::from_array([0u8; 64]), |dig, block| dig ^ u8x64::from_slice(block)),
+            Preceding::Digest(digest) => u8x64::from_array(*digest)
+        };
+        if block as u128 * 64 + ciphertext.len() as u128 > Self::BLOCKS * 64 {
+            return Err(Error::LimitExceeded);
+        }
+
+        self.state[12] = block as u32;
+        if LEGACY {
+            self.state[13] = (block >> 32) as u32;
+        }
+        self.exhausted = block as u128 == Self::BLOCKS;
+        self.offset    = 0;
+        self.prev_dig  = prev_dig;
+        self.try_decrypt_stream(ciphertext)
+    }
+
+    /// Snapshot of the cipher, digest and block being filled by the
+    /// streaming API included, to resume it with `from_state` after a restart
+    pub fn export_state(&self) -> CipherState {
+        let snapshot = Zeroizing::new(Snapshot {
+            state: self.state.to_array(),
+            offset: self.offset,
+            exhausted: self.exhausted,
+            prev_dig: self.prev_dig.to_array(),
+            partial: *self.partial,
+            xorshift: self.xorshift.state.to_array(),
+            xorshift_len: self.xorshift.len
+        });
+        CipherState::new(Kind::DChaCha, R, LEGACY, &snapshot)
+    }
+
+    /// Cipher at the point `state` was exported at, in the middle of a
+    /// message or not.
+    /// Returns `Error::InvalidState` if `state` comes from a ChaCha or a
+    /// DChaCha with another round count or counter layout
+    pub fn from_state(state: &CipherState) -> Result<Self, Error> {
+        let snapshot = state.snapshot(Kind::DChaCha, R, LEGACY)?;
+        let mut cipher = Self::with_state(&[0u8; 32], [0; 4]);
+        cipher.state     = u32x16::from_array(snapshot.state);
+        cipher.exhausted = snapshot.exhausted;
+        cipher.prev_dig  = u8x64::from_array(snapshot.prev_dig);
+        *cipher.partial  = snapshot.partial;
+        cipher.xorshift.state = u32x8::from_array(snapshot.xorshift);
+        cipher.xorshift.len   = snapshot.xorshift_len;
+
+        if snapshot.offset > 0 {
+            // regenerates the keystream of the block being filled, the one
+            // before the counter
+            let block = cipher.next_block() - 1;
+            cipher.state[12] = block as u32;
+            if LEGACY {
+                cipher.state[13] = (block >> 32) as u32;
+            }
+            cipher.exhausted = false;
+            cipher.block_fn();
+            cipher.convert_keystream_to_u8_arr();
+            cipher.offset = snapshot.offset;
+        }
+        Ok(cipher)
+    }
+
+    /// Returns the next raw keystream block without touching the digest
+    pub(crate) fn keystream_block(&mut self) -> [u8; 64] {
+        self.block_fn();
+        self.convert_keystream_to_u8_arr();
+        self.keystream_buffer.to_array()
+    }
+
+    /// Advances the block counter and digest over `ciphertext` exactly like
+    /// `decrypt` would, without producing any plaintext
+    pub(crate) fn skip_ciphertext(&mut self, ciphertext: &[u8]) {
+        for chunk in ciphertext.chunks(64) {
+            self.increment_counter();
             if chunk.len() == 64 {
                 self.prev_dig ^= u8x64::from_slice(chunk);
             } else {
-                self.xorshift.set_seed(chunk.as_ref());
+                self.xorshift.set_seed(chunk);
                 self.xorshift.xor_with_slice(self.prev_dig.as_mut_array());
             }
         }
     }
 
-    pub fn decrypt(&mut self, ciphertext: &mut [u8]) {
-        for chunk in ciphertext.chunks_mut(64) {
-            if chunk.len() != 64 {
-                self.xorshift.set_seed(chunk.as_ref());
-            } else {
-                self.prev_ciph.copy_from_slice(chunk.as_slice());
+    /// Keyed final block: the next keystream block XORed with the running digest
+    pub(crate) fn final_block(&mut self) -> [u8; 64] {
+        self.block_fn();
+        self.convert_keystream_to_u8_arr();
+        (self.keystream_buffer ^ self.prev_dig).to_array()
+    }
+}
+
+
+#[cfg(feature = "std")]
+impl<const R: usize, const LEGACY: bool> DChaCha<R, LEGACY> {
+    /// Encrypts the segments of `bufs` as one message, same as `encrypt` on
+    /// their concatenation
+    ///
+    /// # Panics
+    /// If the keystream runs out, see `try_encrypt_vectored`
+    pub fn encrypt_vectored(&mut self, bufs: &mut [std::io::IoSliceMut<'_>]) {
+        self.try_encrypt_vectored(bufs).expect("DChaCha20 keystream exhausted");
+    }
+
+    /// Decrypts the segments of `bufs` as one message, same as `decrypt` on
+    /// their concatenation
+    ///
+    /// # Panics
+    /// If the keystream runs out, see `try_decrypt_vectored`
+    pub fn decrypt_vectored(&mut self, bufs: &mut [std::io::IoSliceMut<'_>]) {
+        self.try_decrypt_vectored(bufs).expect("DChaCha20 keystream exhausted");
+    }
+
+    /// Fallible `encrypt_vectored`, returns `Error::LimitExceeded` without
+    /// touching any segment if the keystream left is too short
+    pub fn try_encrypt_vectored(&mut self, bufs: &mut [std::io::IoSliceMut<'_>]) -> Result<(), Error> {
+        self.check_vectored(bufs)?;
+        for buf in bufs {
+            self.encrypt_stream(buf);
+        }
+        self.finish();
+        Ok(())
+    }
+
+    /// Fallible `decrypt_vectored`, returns `Error::LimitExceeded` without
+    /// touching any segment if the keystream left is too short
+    pub fn try_decrypt_vectored(&mut self, bufs: &mut [std::io::IoSliceMut<'_>]) -> Result<(), Error> {
+        self.check_vectored(bufs)?;
+        for buf in bufs {
+            self.decrypt_stream(buf);
+        }
+        self.finish();
+        Ok(())
+    }
+
+    fn check_vectored(&self, bufs: &[std::io::IoSliceMut<'_>]) -> Result<(), Error> {
+        let len: u64 = bufs.iter().map(|buf| buf.len() as u64).sum();
+        if len > self.remaining() {
+            return Err(Error::LimitExceeded);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(feature = "rayon")]
+impl<const R: usize, const LEGACY: bool> DChaCha<R, LEGACY> {
+    /// Same as `decrypt`, with the whole blocks spread over the rayon thread
+    /// pool. The plaintext of a block only needs its keystream and the XOR of
+    /// the ciphertext blocks before it, so once the digest at the start of
+    /// each segment is known from a prefix XOR the segments are independent
+    ///
+    /// # Panics
+    /// If the keystream runs out, see `try_decrypt_parallel`
+    pub fn decrypt_parallel(&mut self, ciphertext: &mut [u8]) {
+        self.try_decrypt_parallel(ciphertext).expect("DChaCha20 keystream exhausted");
+    }
+
+    /// Fallible `decrypt_parallel`, returns `Error::LimitExceeded` without
+    /// touching `ciphertext` if the keystream left is too short
+    pub fn try_decrypt_parallel(&mut self, ciphertext: &mut [u8]) -> Result<(), Error> {
+        use rayon::prelude::*;
+
+        if ciphertext.len() as u64 > self.remaining() {
+            return Err(Error::LimitExceeded);
+        }
+
+        let buff = self.continue_block(ciphertext, false);
+        let (blocks, tail) = buff.split_at_mut(buff.len() / 64 * 64);
+        if blocks.len() > PARALLEL_SEGMENT_LEN {
+            let digests: Vec<u8x64> = blocks
+                .par_chunks(PARALLEL_SEGMENT_LEN)
+                .map(|segment| {
+                    segment.chunks_exact(64).fold(u8x64::from_array([0u8; 64]), |dig, block| dig ^ u8x64::from_slice(block))
+                })
+                .collect();
+
+            // digest at the start of each segment
+            let mut starts = Vec::with_capacity(digests.len());
+            for dig in digests {
+                starts.push(self.prev_dig);
+                self.prev_dig ^= dig;
             }
-            self.apply_keystream(chunk);
-            if chunk.len() == 64 {
-                self.prev_dig ^= u8x64::from_slice(self.prev_ciph.as_slice());
-            } else {
-                self.xorshift.xor_with_slice(self.prev_dig.as_mut_array());
+
+            let this = &*self;
+            blocks
+                .par_chunks_mut(PARALLEL_SEGMENT_LEN)
+                .zip(starts)
+                .enumerate()
+                .for_each(|(i, (segment, prev_dig))| {
+                    let mut cipher = this.fork((i * PARALLEL_SEGMENT_LEN / 64) as u64, prev_dig);
+                    cipher.decrypt_stream(segment);
+                });
+
+            for _ in 0..blocks.len() / 64 {
+                self.increment_counter();
             }
+        } else {
+            self.decrypt_stream(blocks);
         }
+
+        self.decrypt_stream(tail);
+        self.finish();
+        Ok(())
+    }
+
+    /// Copy of the cipher `blocks` blocks further in the keystream, on a block
+    /// boundary with `prev_dig` as the digest
+    fn fork(&self, blocks: u64, prev_dig: u8x64) -> Self {
+        let mut cipher = Self {
+            state: self.state,
+            keystream: u32x16::from_array([0u32; 16]),
+            keystream_buffer: u8x64::from_array([0u8; 64]),
+            prev_dig,
+            partial: Zeroizing::new([0u8; 64]),
+            offset: 0,
+            exhausted: self.exhausted,
+            xorshift: XorShiftSIMD::new(),
+            backend: self.backend
+        };
+        let (low, carry) = cipher.state[12].overflowing_add(blocks as u32);
+        cipher.state[12] = low;
+        if LEGACY {
+            cipher.state[13] = cipher.state[13].wrapping_add((blocks >> 32) as u32 + carry as u32);
+        }
+        cipher
+    }
+}
+
+
+impl<const R: usize, const LEGACY: bool> Zeroize for DChaCha<R, LEGACY> {
+    fn zeroize(&mut self) {
+        self.state.as_mut_array().zeroize();
+        self.keystream.as_mut_array().zeroize();
+        self.keystream_buffer.as_mut_array().zeroize();
+        self.prev_dig.as_mut_array().zeroize();
+        self.partial.zeroize();
+        self.offset.zeroize();
+        self.exhausted.zeroize();
+        self.xorshift.zeroize();
+    }
+}
+
+impl<const R: usize, const LEGACY: bool> Drop for DChaCha<R, LEGACY> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<const R: usize, const LEGACY: bool> ZeroizeOnDrop for DChaCha<R, LEGACY> {}
+
+
+impl DChaCha20 {
+    /// XDChaCha20: DChaCha20 keyed with the HChaCha20 subkey of the first
+    /// 16 bytes of the 24 bytes nonce, same as XChaCha20
+    pub fn new_xdchacha20(key: &[u8; 32], nonce: &[u8; 24]) -> Self {
+        let (subkey, nonce) = xchacha20_subkey(key, nonce);
+        Self::new(&subkey, &nonce)
     }
 }
 
@@ -195,7 +739,7 @@ impl XorShiftSIMD {
                 *b ^= (*s & 255) as u8;
             }
         } else if self.len < 32 {
-            let mut s = u32x4::from_slice(self.state.as_array()[0..4].as_slice());
+            let mut s = u32x4::from_slice(&self.state.as_array()[0..4]);
             let mut p = 0;
             for _ in 0..16 {
                 s ^= s << 13;
@@ -207,7 +751,7 @@ impl XorShiftSIMD {
                 }
             }
         } else {
-            let mut s = u32x8::from_slice(self.state.as_array()[0..8].as_slice());
+            let mut s = u32x8::from_slice(&self.state.as_array()[0..8]);
             let mut p = 0;
             for _ in 0..8 {
                 s ^= s << 13;
@@ -222,12 +766,34 @@ impl XorShiftSIMD {
     }
 }
 
+impl Zeroize for XorShiftSIMD {
+    fn zeroize(&mut self) {
+        self.state.as_mut_array().zeroize();
+        self.len.zeroize();
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::{mem::MaybeUninit, ptr};
+
     use hex_literal::hex;
     use rand::RngCore;
 
-    use super::DChaCha20;
+    use crate::{test_util::{contains, field, fields_after_drop}, Backend, ChaCha20, CipherState, Error};
+    use super::{DChaCha8, DChaCha12, DChaCha20, DChaCha20Legacy, Preceding};
+
+    /// Key and keystream fields of a cipher
+    fn secret_fields(cipher: *const DChaCha20) -> Vec<(*const u8, usize)> {
+        // SAFETY: only computes field addresses
+        unsafe {
+            vec![
+                field(ptr::addr_of!((*cipher).state)), field(ptr::addr_of!((*cipher).keystream)),
+                field(ptr::addr_of!((*cipher).keystream_buffer)), field(ptr::addr_of!((*cipher).prev_dig)),
+                field(ptr::addr_of!((*cipher).partial)), field(ptr::addr_of!((*cipher).xorshift.state))
+            ]
+        }
+    }
 
     #[test]
     fn validate() {
@@ -308,4 +874,457 @@ mod tests {
         }
     }
 
+    #[test]
+    fn streaming() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let msgs = [b"hello".repeat(300), b"hello".repeat(7), b"hello".repeat(64)];
+        let mut expected = msgs.clone();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        for msg in &mut expected {
+            cipher.encrypt(msg);
+        }
+
+        let splits: [&[usize]; 4] = [&[10, 10, 10], &[1, 63, 64], &[64, 1, 200], &[100, 28, 3]];
+        for sizes in splits {
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            let mut cipher1 = DChaCha20::new(&key, &nonce);
+            for (msg, expected) in msgs.iter().zip(&expected) {
+                let mut buffer = msg.clone();
+                let mut pos = 0;
+                for size in sizes.iter().cycle() {
+                    let end = (pos + size).min(buffer.len());
+                    cipher.encrypt_stream(&mut buffer[pos..end]);
+                    pos = end;
+                    if pos == buffer.len() {
+                        break;
+                    }
+                }
+                cipher.finish();
+                assert_eq!(&buffer, expected);
+
+                // decrypt with a different segmentation
+                for chunk in buffer.chunks_mut(sizes[0] + 7) {
+                    cipher1.decrypt_stream(chunk);
+                }
+                cipher1.finish();
+                assert_eq!(&buffer, msg);
+            }
+        }
+    }
+
+    #[test]
+    fn limit() {
+        let key = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        let nonce = hex!("00 00 00 00 00 00 00 4a 00 00 00 00");
+
+        let cipher = DChaCha20::new(&key, &nonce);
+        assert_eq!(cipher.remaining(), 64 << 32);
+
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        let mut cipher1 = DChaCha20::new(&key, &nonce);
+        cipher.state[12]  = u32::MAX - 1;
+        cipher1.state[12] = u32::MAX - 1;
+        assert_eq!(cipher.remaining(), 128);
+
+        let msg = [0x42u8; 129];
+        let mut buffer = msg;
+        assert_eq!(cipher.try_encrypt(&mut buffer), Err(Error::LimitExceeded));
+        assert_eq!(buffer, msg);
+
+        cipher.try_encrypt_stream(&mut buffer[..70]).unwrap();
+        assert_eq!(cipher.remaining(), 58);
+        cipher.try_encrypt_stream(&mut buffer[70..128]).unwrap();
+        assert_eq!(cipher.remaining(), 0);
+        assert_eq!(cipher.try_encrypt_stream(&mut buffer[128..]), Err(Error::LimitExceeded));
+        assert_eq!(cipher.try_encrypt(&mut buffer[128..]), Err(Error::LimitExceeded));
+        cipher.finish();
+        assert_eq!(cipher.remaining(), 0);
+
+        assert_eq!(cipher1.try_decrypt(&mut buffer), Err(Error::LimitExceeded));
+        cipher1.try_decrypt(&mut buffer[..128]).unwrap();
+        assert_eq!(buffer[..128], msg[..128]);
+        assert_eq!(cipher1.try_decrypt(&mut buffer[128..]), Err(Error::LimitExceeded));
+    }
+
+    #[test]
+    #[should_panic(expected = "keystream exhausted")]
+    fn limit_panics() {
+        let mut cipher = DChaCha20::new(&[0u8; 32], &[0u8; 12]);
+        cipher.state[12] = u32::MAX;
+        cipher.encrypt(&mut [0u8; 65]);
+    }
+
+    #[test]
+    fn xdchacha20() {
+        let key = hex!("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = hex!("404142434445464748494a4b4c4d4e4f5051525354555658");
+
+        let msg = b"The dhole (pronounced \"dole\") is also known as the Asiatic wild dog, red dog, and whistling dog. It is about the size of a German shepherd but looks more like a long-legged fox. This highly elusive and skilled jumper is classified with wolves, coyotes, jackals, and foxes in the taxonomic family Canidae.".to_vec();
+
+        let mut buffer = msg.clone();
+        DChaCha20::new_xdchacha20(&key, &nonce).encrypt(&mut buffer);
+
+        // first block has no feedback yet, so it matches XChaCha20 (draft-irtf-cfrg-xchacha appendix A.3.2)
+        assert_eq!(buffer[..64], hex!("4559abba4e48c16102e8bb2c05e6947f50a786de162f9b0b7e592a9b53d0d4e98d8d6410d540a1a6375b26d80dace4fab52384c731acbf16a5923c0c48d3575d"));
+        assert_eq!(buffer, hex!("4559abba4e48c16102e8bb2c05e6947f50a786de162f9b0b7e592a9b53d0d4e98d8d6410d540a1a6375b26d80dace4fab52384c731acbf16a5923c0c48d3575d085487dd752eaecb71f8da0b72e79d453b502786bea9d999da456278fa641433616d9cc9584ddfa3842116a3b6cad7cbd1cf1adc1546b27a9a6fe0e007bbb0197d5b355d38ae7f4b6054ab21afec831190e92c106720fe9c10fe0ea8dd35829a907fbbc3637be765522198c2d3f6755f96a9eb82a1c988aa5e0a8e226f913d4839fd7223dcbcf78b86d2be40203d776aa5656362dd07a8ccba576f6193220bee2be763cfbc7c865f5771bf7702ee2e948e3c64840d4317cda1bfbd8dde7d9260c22ca946d594a29a597793de624a5f61fcf033623546456044d875483033efcdc4c111c493af42e8f43007d6230aaf3f"));
+
+        DChaCha20::new_xdchacha20(&key, &nonce).decrypt(&mut buffer);
+        assert_eq!(buffer, msg);
+    }
+
+    #[test]
+    fn reduced_rounds() {
+        let key = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        let nonce = hex!("00 00 00 00 00 00 00 4a 00 00 00 00");
+
+        // first block has no feedback yet, so it is the plain ChaCha8/ChaCha12 keystream
+        let mut buffer = [0u8; 150];
+        DChaCha8::new(&key, &nonce).encrypt(&mut buffer);
+        assert_eq!(buffer[..64], hex!("5a6e23747061917868d31dc7c1e7203a98b59ada28b2bd8224cb22c7a5f4ad4c25164778375760dbe4a871d1d15530593db7d8f154e6687b73acc465e7aa5fd0"));
+        assert_eq!(buffer, hex!("5a6e23747061917868d31dc7c1e7203a98b59ada28b2bd8224cb22c7a5f4ad4c25164778375760dbe4a871d1d15530593db7d8f154e6687b73acc465e7aa5fd0e666dda7884dc66436a96d41a46f8ed8195b82b220db14408b546ce3efbefb7b44a498909010ba26b787f54784662101a70ae630bfa31e7e37db634cbc289467947a9eae7e956a9cbd9d8e214d8e9afd20ff9a511be7"));
+        DChaCha8::new(&key, &nonce).decrypt(&mut buffer);
+        assert_eq!(buffer, [0u8; 150]);
+
+        DChaCha12::new(&key, &nonce).encrypt(&mut buffer);
+        assert_eq!(buffer[..64], hex!("631c0ceaad4a393c070ed70ca805409e22aa63e516c26b9fd8f770d1d58356637f66bafb595ddda4c516742e0dbcca80f6148412a7f94130c990837f9d82abee"));
+        assert_eq!(buffer, hex!("631c0ceaad4a393c070ed70ca805409e22aa63e516c26b9fd8f770d1d58356637f66bafb595ddda4c516742e0dbcca80f6148412a7f94130c990837f9d82abeea23a8ad5383d6caf0f77b8f4b24125c5f1f800e9237f2073135a3bbe08f836ecf4ce8ae7634352a2864169902ce4f7df943ee4e695e75373711ac4e9aded3accd04b7a357d8f6e50a46262540322c2c4cf92fca19302"));
+        DChaCha12::new(&key, &nonce).decrypt(&mut buffer);
+        assert_eq!(buffer, [0u8; 150]);
+    }
+
+    #[test]
+    fn legacy() {
+        let key = hex!("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11 12 13 14 15 16 17 18 19 1a 1b 1c 1d 1e 1f");
+        let nonce = hex!("00 00 00 00 00 00 00 4a");
+
+        // across the carry into word 13: the first block is the legacy
+        // ChaCha20 keystream, the second one is XORed with it as digest
+        let mut cipher = DChaCha20Legacy::new(&key, &nonce);
+        cipher.state[12] = u32::MAX;
+        let mut buffer = [0u8; 128];
+        cipher.encrypt(&mut buffer);
+        assert_eq!(buffer[..64], hex!("5ac635c23440ac375aa7fd28de550428b3af38c7a5c7026a9eccc31aeea51ae2023908a4a1c1f6a5c1c8820936878652ec585fdcb72df00c1583d0efea883ce1"));
+        assert_eq!(buffer[64..], hex!("cc36d9bd2eecc448007297ae3b7aa101fb4951f271d8d0f0f20a20d2346613a4cc43f9bf4bfa3f824a0b2b1ef07d18ba2dab9ee23802ebb3214d8fae35997a97"));
+        assert_eq!(cipher.state[13], 1);
+
+        let mut cipher = DChaCha20Legacy::new(&key, &nonce);
+        cipher.state[12] = u32::MAX;
+        cipher.decrypt(&mut buffer);
+        assert_eq!(buffer, [0u8; 128]);
+
+        let mut cipher = DChaCha20Legacy::new(&key, &nonce);
+        assert_eq!(cipher.remaining(), u64::MAX);
+        cipher.state[12] = u32::MAX;
+        cipher.state[13] = u32::MAX;
+        assert_eq!(cipher.remaining(), 64);
+        assert_eq!(cipher.try_encrypt(&mut [0u8; 65]), Err(Error::LimitExceeded));
+        cipher.try_encrypt(&mut [0u8; 64]).unwrap();
+        assert_eq!(cipher.remaining(), 0);
+    }
+
+    #[test]
+    fn backends() {
+        let key = [7u8; 32];
+        let nonce = [9u8; 12];
+        let msg = b"hello".repeat(2000);
+
+        // one block per call never goes through the backends
+        let mut expected = msg.clone();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        for chunk in expected.chunks_mut(64) {
+            cipher.encrypt_stream(chunk);
+        }
+        cipher.finish();
+
+        assert_eq!(DChaCha20::new(&key, &nonce).backend(), Backend::detect());
+        for backend in Backend::ALL.into_iter().filter(|b| b.is_supported()) {
+            let mut buffer = msg.clone();
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            cipher.set_backend(backend);
+            cipher.encrypt(&mut buffer);
+            assert_eq!(buffer, expected, "{backend:?}");
+
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            cipher.set_backend(backend);
+            cipher.decrypt_stream(&mut buffer[..5]);
+            cipher.decrypt_stream(&mut buffer[5..]);
+            cipher.finish();
+            assert_eq!(buffer, msg, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn decrypt_at() {
+        let key = [7u8; 32];
+        let nonce = [9u8; 12];
+        let msg = (0..1000).map(|i| i as u8).collect::<Vec<u8>>();
+
+        // digests saved every 4 blocks while encrypting
+        let mut ciphertext = msg.clone();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        let mut digests = Vec::new();
+        for chunk in ciphertext.chunks_mut(256) {
+            digests.push(cipher.digest());
+            cipher.encrypt_stream(chunk);
+        }
+        cipher.finish();
+
+        for (i, digest) in digests.iter().enumerate() {
+            let start = i * 256;
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            let mut buffer = ciphertext[start..].to_vec();
+            cipher.decrypt_at(&mut buffer, (start / 64) as u64, Preceding::Digest(digest)).unwrap();
+            assert_eq!(buffer, msg[start..]);
+        }
+
+        for start in [0, 64, 128, 640, 960] {
+            let end = (start + 100).min(msg.len());
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            let mut buffer = ciphertext[start..end].to_vec();
+            cipher.decrypt_at(&mut buffer, (start / 64) as u64, Preceding::Ciphertext(&ciphertext[..start])).unwrap();
+            assert_eq!(buffer, msg[start..end]);
+            let mut expected = [0u8; 64];
+            for block in ciphertext[..end / 64 * 64].chunks(64) {
+                expected.iter_mut().zip(block).for_each(|(d, c)| *d ^= c);
+            }
+            assert_eq!(cipher.digest(), expected);
+
+            // the rest of the message follows on
+            let mut rest = ciphertext[end..].to_vec();
+            cipher.decrypt(&mut rest);
+            assert_eq!(rest, msg[end..]);
+        }
+
+        // message starting at block 1, as in DChaCha20Aead
+        let mut ciphertext = msg.clone();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.keystream_block();
+        cipher.encrypt(&mut ciphertext);
+        let mut buffer = ciphertext[320..].to_vec();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.decrypt_at(&mut buffer, 6, Preceding::Ciphertext(&ciphertext[..320])).unwrap();
+        assert_eq!(buffer, msg[320..]);
+
+        let mut buffer = ciphertext[320..].to_vec();
+        assert_eq!(cipher.decrypt_at(&mut buffer, 6, Preceding::Ciphertext(&ciphertext[..300])), Err(Error::InvalidLength));
+        assert_eq!(cipher.decrypt_at(&mut buffer, u32::MAX as u64, Preceding::Digest(&[0u8; 64])), Err(Error::LimitExceeded));
+        assert_eq!(buffer, ciphertext[320..]);
+        cipher.decrypt_at(&mut buffer[..64], u32::MAX as u64, Preceding::Digest(&[0u8; 64])).unwrap();
+        assert_eq!(cipher.remaining(), 0);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn decrypt_parallel() {
+        let key = [7u8; 32];
+        let nonce = [9u8; 12];
+
+        for len in [0, 100, 64 * 1024, 64 * 1024 + 64, 300_000, 1 << 20] {
+            let msg = (0..len).map(|i| i as u8).collect::<Vec<u8>>();
+            let mut ciphertext = msg.clone();
+            DChaCha20::new(&key, &nonce).encrypt(&mut ciphertext);
+
+            let mut buffer = ciphertext.clone();
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            cipher.decrypt_parallel(&mut buffer);
+            assert_eq!(buffer, msg, "{len}");
+
+            // same state afterwards, whole message at a time or mid block
+            let mut sequential = DChaCha20::new(&key, &nonce);
+            sequential.decrypt(&mut ciphertext.clone());
+            let mut next = b"hello".repeat(30);
+            let mut expected = next.clone();
+            cipher.encrypt(&mut next);
+            sequential.encrypt(&mut expected);
+            assert_eq!(next, expected, "{len}");
+
+            if len > 10 {
+                let mut buffer = ciphertext.clone();
+                let mut cipher = DChaCha20::new(&key, &nonce);
+                cipher.decrypt_stream(&mut buffer[..10]);
+                cipher.decrypt_parallel(&mut buffer[10..]);
+                assert_eq!(buffer, msg, "{len}");
+            }
+        }
+
+        // 64 bit counter carrying in the middle of the buffer
+        let msg = vec![0x5a; 1 << 20];
+        let mut ciphertext = msg.clone();
+        let mut cipher = DChaCha20Legacy::new(&key, &[9u8; 8]);
+        cipher.state[12] = u32::MAX - 100;
+        cipher.encrypt(&mut ciphertext);
+        let mut cipher = DChaCha20Legacy::new(&key, &[9u8; 8]);
+        cipher.state[12] = u32::MAX - 100;
+        cipher.decrypt_parallel(&mut ciphertext);
+        assert_eq!(ciphertext, msg);
+        assert_eq!((cipher.state[12], cipher.state[13]), (16384 - 101, 1));
+
+        // end of the 32 bit counter
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.state[12] = u32::MAX - 2047;
+        assert_eq!(cipher.try_decrypt_parallel(&mut vec![0u8; 2048 * 64 + 1]), Err(Error::LimitExceeded));
+        cipher.decrypt_parallel(&mut vec![0u8; 2048 * 64]);
+        assert_eq!(cipher.remaining(), 0);
+    }
+
+    #[test]
+    fn b2b() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let msg = b"hello".repeat(300);
+        let mut expected = msg.clone();
+        let mut expected2 = b"hi".to_vec();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.encrypt(&mut expected);
+        cipher.encrypt(&mut expected2);
+
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        let mut ciphertext = vec![0u8; msg.len()];
+        cipher.encrypt_b2b(&msg, &mut ciphertext).unwrap();
+        assert_eq!(ciphertext, expected);
+        let mut ciphertext = [MaybeUninit::uninit(); 2];
+        assert_eq!(cipher.encrypt_b2b_uninit(b"hi", &mut ciphertext).unwrap(), expected2);
+
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        let mut plaintext = vec![0u8; msg.len()];
+        assert_eq!(cipher.decrypt_b2b(&expected, &mut plaintext[1..]), Err(Error::InvalidLength));
+        cipher.decrypt_b2b(&expected, &mut plaintext).unwrap();
+        assert_eq!(plaintext, msg);
+        let mut plaintext = [MaybeUninit::uninit(); 2];
+        assert_eq!(cipher.decrypt_b2b_uninit(&expected2, &mut plaintext).unwrap(), b"hi");
+
+        let mut cipher = DChaCha20Legacy::new(&key, &[0u8; 8]);
+        cipher.decrypt_at(&mut [], u64::MAX, Preceding::Digest(&[0u8; 64])).unwrap();
+        let mut plaintext = [0u8; 65];
+        assert_eq!(cipher.decrypt_b2b(&[1u8; 65], &mut plaintext), Err(Error::LimitExceeded));
+        assert_eq!(plaintext, [0u8; 65]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn vectored() {
+        use std::io::IoSliceMut;
+
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let msg = b"hello".repeat(300);
+        let mut expected = msg.clone();
+        let mut expected2 = b"hi".to_vec();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.encrypt(&mut expected);
+        cipher.encrypt(&mut expected2);
+
+        let mut buffer = msg.clone();
+        let mut buffer2 = b"hi".to_vec();
+        let (a, rest) = buffer.split_at_mut(3);
+        let (b, rest) = rest.split_at_mut(0);
+        let (c, d) = rest.split_at_mut(700);
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.encrypt_vectored(&mut [IoSliceMut::new(a), IoSliceMut::new(b), IoSliceMut::new(c), IoSliceMut::new(d)]);
+        cipher.encrypt_vectored(&mut [IoSliceMut::new(&mut buffer2)]);
+        assert_eq!(buffer, expected);
+        assert_eq!(buffer2, expected2);
+
+        let (a, b) = buffer.split_at_mut(65);
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.decrypt_vectored(&mut [IoSliceMut::new(a), IoSliceMut::new(b)]);
+        cipher.decrypt_vectored(&mut [IoSliceMut::new(&mut buffer2[..1]), IoSliceMut::new(&mut [])]);
+        assert_eq!(buffer, msg);
+        assert_eq!(buffer2[0], b'h');
+
+        let mut cipher = DChaCha20Legacy::new(&key, &[0u8; 8]);
+        cipher.decrypt_at(&mut [], u64::MAX, Preceding::Digest(&[0u8; 64])).unwrap();
+        let (a, b) = buffer.split_at_mut(60);
+        assert_eq!(cipher.try_encrypt_vectored(&mut [IoSliceMut::new(a), IoSliceMut::new(&mut b[..5])]), Err(Error::LimitExceeded));
+        assert_eq!(buffer, msg);
+    }
+
+    #[test]
+    fn export_state() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        // a short message first, so the digest carries the xorshift of its last block
+        let mut first = b"hi there".to_vec();
+        let msg = b"hello".repeat(300);
+        let mut expected = msg.clone();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.encrypt(&mut first);
+        cipher.encrypt(&mut expected);
+        let mut after = [0u8; 100];
+        cipher.encrypt(&mut after);
+
+        for split in [0, 1, 63, 64, 65, 500, 1499, 1500] {
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            cipher.encrypt(&mut b"hi there".to_vec());
+            let mut buffer = msg.clone();
+            cipher.encrypt_stream(&mut buffer[..split]);
+
+            let bytes = cipher.export_state().as_bytes().to_vec();
+            drop(cipher);
+            let mut cipher = DChaCha20::from_state(&CipherState::from_bytes(&bytes).unwrap()).unwrap();
+            cipher.encrypt(&mut buffer[split..]);
+            assert_eq!(buffer, expected);
+
+            let mut buffer = [0u8; 100];
+            cipher.encrypt(&mut buffer);
+            assert_eq!(buffer, after);
+        }
+
+        // resumed in the middle of decrypting
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.decrypt(&mut first);
+        let mut buffer = expected.clone();
+        cipher.decrypt_stream(&mut buffer[..100]);
+        let mut cipher = DChaCha20::from_state(&cipher.export_state()).unwrap();
+        cipher.decrypt(&mut buffer[100..]);
+        assert_eq!(buffer, msg);
+
+        let mut cipher = DChaCha20Legacy::new(&key, &[7u8; 8]);
+        cipher.encrypt_stream(&mut [0u8; 70]);
+        let mut resumed = DChaCha20Legacy::from_state(&cipher.export_state()).unwrap();
+        let (mut a, mut b) = ([0u8; 100], [0u8; 100]);
+        cipher.encrypt(&mut a);
+        resumed.encrypt(&mut b);
+        assert_eq!(a, b);
+
+        let state = DChaCha20::new(&key, &nonce).export_state();
+        assert_eq!(DChaCha12::from_state(&state).err(), Some(Error::InvalidState));
+        assert_eq!(DChaCha20Legacy::from_state(&state).err(), Some(Error::InvalidState));
+        assert_eq!(ChaCha20::from_state(&state).err(), Some(Error::InvalidState));
+    }
+
+    #[test]
+    fn zeroize_on_drop() {
+        let key = [0xa5u8; 32];
+        let nonce = [0x5au8; 12];
+        let mut ciphertext = [0u8; 100];
+        DChaCha20::new(&key, &nonce).encrypt_stream(&mut ciphertext);
+        // keystream of block 1, before the digest of block 0 is applied
+        let keystream: Vec<u8> = ciphertext[64..].iter().zip(&ciphertext[..36]).map(|(c, d)| c ^ d).collect();
+
+        // leaves the digest, the partial block and its keystream in the cipher
+        let (live, dropped) = fields_after_drop(DChaCha20::new(&key, &nonce), |cipher| cipher.encrypt_stream(&mut [0u8; 100]), secret_fields);
+
+        for secret in [&key[..16], &ciphertext[..16], &ciphertext[64..80], &keystream[..16]] {
+            assert!(contains(&live, secret));
+            assert!(!contains(&dropped, secret));
+        }
+    }
 }
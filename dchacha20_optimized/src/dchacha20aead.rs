@@ -0,0 +1,180 @@
+use zeroize::Zeroizing;
+
+use crate::{chacha20poly1305::ct_eq, dchacha20::DChaCha20, error::Error, poly1305::Poly1305};
+
+/// Authenticated DChaCha20.
+///
+/// Block 0 of the keystream is the one-time Poly1305 key and the message is
+/// encrypted from block 1, as in RFC 8439. Once the message is processed the
+/// next keystream block is XORed with the feedback digest (`prev_dig`) to form
+/// the keyed final block. The tag is then
+///
+/// ```text
+/// Poly1305(aad || pad16 || ciphertext || pad16 || final block || len(aad) || len(ciphertext))
+/// ```
+///
+/// The digest is only a XOR of ciphertext blocks, so on its own two identical
+/// bit flips in different blocks would cancel out; the ciphertext is fed to
+/// Poly1305 as well to rule that out.
+pub struct DChaCha20Aead {
+    key: Zeroizing<[u8; 32]>
+}
+
+impl DChaCha20Aead {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            key: Zeroizing::new(*key)
+        }
+    }
+
+    fn init(&self, nonce: &[u8; 12]) -> (DChaCha20, Poly1305) {
+        let mut cipher = DChaCha20::new(&self.key, nonce);
+        let block0 = Zeroizing::new(cipher.keystream_block());
+
+        let poly = Poly1305::new(block0[..32].try_into().unwrap());
+        (cipher, poly)
+    }
+
+    fn compute_tag(cipher: &mut DChaCha20, mut poly: Poly1305, aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+        let final_block = Zeroizing::new(cipher.final_block());
+
+        poly.update(aad);
+        poly.pad();
+        poly.update(ciphertext);
+        poly.pad();
+        poly.update(final_block.as_slice());
+        poly.update(&(aad.len() as u64).to_le_bytes());
+        poly.update(&(ciphertext.len() as u64).to_le_bytes());
+        poly.finalize()
+    }
+
+    /// Encrypts `buffer` in place and returns the authentication tag
+    /// covering both `aad` and the ciphertext
+    ///
+    /// # Panics
+    /// If `buffer` is longer than 2^38 - 128 bytes, the keystream left once
+    /// the Poly1305 key and the final block are taken out, see `try_encrypt`
+    pub fn encrypt(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8]) -> [u8; 16] {
+        self.try_encrypt(nonce, aad, buffer).expect("DChaCha20 keystream exhausted")
+    }
+
+    /// Fallible `encrypt`, returns `Error::LimitExceeded` without touching
+    /// `buffer` if it is longer than 2^38 - 128 bytes
+    pub fn try_encrypt(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8]) -> Result<[u8; 16], Error> {
+        let (mut cipher, poly) = self.init(nonce);
+        if buffer.len() as u64 > cipher.remaining() - 64 {
+            return Err(Error::LimitExceeded);
+        }
+        cipher.encrypt(buffer);
+        Ok(Self::compute_tag(&mut cipher, poly, aad, buffer))
+    }
+
+    /// Checks `tag` and decrypts `buffer` in place.
+    /// On mismatch `buffer` is left untouched and `Error::TagMismatch` is returned
+    pub fn decrypt(&self, nonce: &[u8; 12], aad: &[u8], buffer: &mut [u8], tag: &[u8; 16]) -> Result<(), Error> {
+        // The digest only depends on the ciphertext, so the tag can be
+        // checked before any plaintext is produced
+        let (mut verifier, poly) = self.init(nonce);
+        if buffer.len() as u64 > verifier.remaining() - 64 {
+            return Err(Error::LimitExceeded);
+        }
+        verifier.skip_ciphertext(buffer);
+        let expected = Self::compute_tag(&mut verifier, poly, aad, buffer);
+        if !ct_eq(&expected, tag) {
+            return Err(Error::TagMismatch);
+        }
+
+        let (mut cipher, _) = self.init(nonce);
+        cipher.try_decrypt(buffer)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use rand::RngCore;
+
+    use crate::Error;
+    use super::DChaCha20Aead;
+
+    #[test]
+    fn validate() {
+        let key = hex!("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = hex!("07 00 00 00 40 41 42 43 44 45 46 47");
+        let aad = hex!("50 51 52 53 c0 c1 c2 c3 c4 c5 c6 c7");
+
+        let msg = "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".as_bytes().to_vec();
+
+        let aead = DChaCha20Aead::new(&key);
+        let mut buffer = msg.clone();
+        let tag = aead.encrypt(&nonce, &aad, &mut buffer);
+
+        // First block is plain ChaCha20 from block 1, same as RFC 8439 section 2.8.2
+        assert_eq!(buffer[..64], hex!("d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36"));
+        assert_eq!(buffer, hex!("d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3641c7304b49f9eb57e385015f7be6659a5e1ec9b5d3b87d6afc67352c7edfb56a024a7aae02e21d8f678c290c5c5cb4c07b67"));
+        assert_eq!(tag, hex!("1c33383ddcaccca03e3a80ef1d288a53"));
+
+        aead.decrypt(&nonce, &aad, &mut buffer, &tag).unwrap();
+        assert_eq!(buffer, msg);
+    }
+
+    #[test]
+    fn tampering() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let aead = DChaCha20Aead::new(&key);
+        let msg  = b"hello".repeat(30);
+        let mut buffer = msg.clone();
+        let tag = aead.encrypt(&nonce, b"header", &mut buffer);
+        let ciphertext = buffer.clone();
+
+        buffer[17] ^= 1;
+        assert_eq!(aead.decrypt(&nonce, b"header", &mut buffer, &tag), Err(Error::TagMismatch));
+
+        // Same flip in the next block leaves the digest unchanged
+        buffer[17 + 64] ^= 1;
+        assert_eq!(aead.decrypt(&nonce, b"header", &mut buffer, &tag), Err(Error::TagMismatch));
+        buffer[17] ^= 1;
+        buffer[17 + 64] ^= 1;
+        assert_eq!(buffer, ciphertext);
+
+        // Partial last block
+        buffer[140] ^= 1;
+        assert_eq!(aead.decrypt(&nonce, b"header", &mut buffer, &tag), Err(Error::TagMismatch));
+        buffer[140] ^= 1;
+
+        assert_eq!(aead.decrypt(&nonce, b"headex", &mut buffer, &tag), Err(Error::TagMismatch));
+        assert_eq!(aead.decrypt(&nonce, b"header", &mut buffer[..128], &tag), Err(Error::TagMismatch));
+
+        let mut bad_tag = tag;
+        bad_tag[15] ^= 0x80;
+        assert_eq!(aead.decrypt(&nonce, b"header", &mut buffer, &bad_tag), Err(Error::TagMismatch));
+        assert_eq!(buffer, ciphertext);
+
+        aead.decrypt(&nonce, b"header", &mut buffer, &tag).unwrap();
+        assert_eq!(buffer, msg);
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let aead = DChaCha20Aead::new(&key);
+        for len in [0, 1, 15, 16, 63, 64, 65, 128, 300] {
+            let msg = vec![0x5a; len];
+            let mut buffer = msg.clone();
+            let tag = aead.encrypt(&nonce, &[], &mut buffer);
+            aead.decrypt(&nonce, &[], &mut buffer, &tag).unwrap();
+            assert_eq!(buffer, msg);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use core::fmt;
+
+/// Errors returned by the fallible cipher APIs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Authentication tag did not match, the message was altered or the
+    /// wrong key/nonce/associated data was used
+    TagMismatch,
+    /// The block counter would run past its last value and repeat the keystream
+    LimitExceeded,
+    /// A buffer does not have the length the operation expects
+    InvalidLength,
+    /// A saved cipher state is malformed or belongs to another cipher
+    InvalidState,
+    /// An encrypted container ends before its final chunk
+    Truncated,
+    /// An encrypted container header is malformed, or data follows the final chunk
+    InvalidContainer,
+    /// A container was written with a password, not a key
+    PasswordRequired,
+    /// A container was written with a key, not a password
+    KeyRequired,
+    /// Key derivation parameters are below the accepted minimum
+    WeakKdf,
+    /// Key derivation parameters are above the accepted maximum, or refused
+    /// by the function itself
+    InvalidKdf
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TagMismatch => write!(f, "authentication tag mismatch"),
+            Error::LimitExceeded => write!(f, "keystream limit exceeded"),
+            Error::InvalidLength => write!(f, "invalid buffer length"),
+            Error::InvalidState => write!(f, "invalid cipher state"),
+            Error::Truncated => write!(f, "truncated container"),
+            Error::InvalidContainer => write!(f, "invalid container"),
+            Error::PasswordRequired => write!(f, "container requires a password"),
+            Error::KeyRequired => write!(f, "container requires a key"),
+            Error::WeakKdf => write!(f, "key derivation parameters too weak"),
+            Error::InvalidKdf => write!(f, "invalid key derivation parameters")
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
@@ -0,0 +1,443 @@
+//! `std::io` adapters encrypting or decrypting everything going through them.
+//!
+//! Writers buffer data and hand it to the cipher a few kilobytes at a time,
+//! readers pass on whatever the inner reader yields. Both ciphers stream
+//! byte-accurately, so a flush in the middle of a block does not change the
+//! ciphertext: the rest of the block continues the same keystream (and for
+//! DChaCha the same digest) on the next write, and the reader on the other
+//! end gets the flushed bytes without waiting for the block to fill up.
+
+use std::io::{self, Read, Write};
+
+use zeroize::Zeroizing;
+
+use crate::{chacha20::ChaCha, dchacha20::DChaCha, error::Error};
+
+/// Encrypts what is written to it into the inner writer
+pub type EncryptWriter<C, W> = CipherWriter<C, W, true>;
+/// Decrypts what is written to it into the inner writer
+pub type DecryptWriter<C, W> = CipherWriter<C, W, false>;
+/// Reads the encryption of what the inner reader yields
+pub type EncryptReader<C, R> = CipherReader<C, R, true>;
+/// Reads the decryption of what the inner reader yields
+pub type DecryptReader<C, R> = CipherReader<C, R, false>;
+
+/// Bytes buffered before going through the cipher
+pub(crate) const BUFFER_LEN: usize = 64 * 128;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Ciphers the adapters can drive, `ChaCha` and `DChaCha`
+pub trait StreamingCipher: sealed::Sealed {
+    /// Encrypts the next part of the stream
+    fn encrypt_part(&mut self, buff: &mut [u8]) -> Result<(), Error>;
+    /// Decrypts the next part of the stream
+    fn decrypt_part(&mut self, buff: &mut [u8]) -> Result<(), Error>;
+    /// Ends the stream, which for DChaCha folds its final partial block
+    /// into the digest
+    fn end(&mut self);
+}
+
+impl<const R: usize, const LEGACY: bool> sealed::Sealed for ChaCha<R, LEGACY> {}
+
+impl<const R: usize, const LEGACY: bool> StreamingCipher for ChaCha<R, LEGACY> {
+    fn encrypt_part(&mut self, buff: &mut [u8]) -> Result<(), Error> {
+        self.try_encrypt(buff)
+    }
+
+    fn decrypt_part(&mut self, buff: &mut [u8]) -> Result<(), Error> {
+        self.try_decrypt(buff)
+    }
+
+    fn end(&mut self) {}
+}
+
+impl<const R: usize, const LEGACY: bool> sealed::Sealed for DChaCha<R, LEGACY> {}
+
+impl<const R: usize, const LEGACY: bool> StreamingCipher for DChaCha<R, LEGACY> {
+    fn encrypt_part(&mut self, buff: &mut [u8]) -> Result<(), Error> {
+        self.try_encrypt_stream(buff)
+    }
+
+    fn decrypt_part(&mut self, buff: &mut [u8]) -> Result<(), Error> {
+        self.try_decrypt_stream(buff)
+    }
+
+    fn end(&mut self) {
+        self.finish();
+    }
+}
+
+/// Lets the adapters borrow a cipher, which then carries on with the next
+/// message once they are done
+impl<C: StreamingCipher> sealed::Sealed for &mut C {}
+
+impl<C: StreamingCipher> StreamingCipher for &mut C {
+    fn encrypt_part(&mut self, buff: &mut [u8]) -> Result<(), Error> {
+        (**self).encrypt_part(buff)
+    }
+
+    fn decrypt_part(&mut self, buff: &mut [u8]) -> Result<(), Error> {
+        (**self).decrypt_part(buff)
+    }
+
+    fn end(&mut self) {
+        (**self).end();
+    }
+}
+
+pub(crate) fn apply<C: StreamingCipher, const ENCRYPT: bool>(cipher: &mut C, buff: &mut [u8]) -> io::Result<()> {
+    let result = if ENCRYPT { cipher.encrypt_part(buff) } else { cipher.decrypt_part(buff) };
+    result.map_err(io::Error::other)
+}
+
+/// Writer passing everything through `C` before the inner writer, see the
+/// `EncryptWriter` and `DecryptWriter` aliases.
+///
+/// `flush` pushes out everything written so far, partial block included.
+/// `finish` ends the message; dropping the writer flushes and ends it as well,
+/// but ignores errors
+pub struct CipherWriter<C: StreamingCipher, W: Write, const ENCRYPT: bool> {
+    cipher: C,
+    /// Taken by `finish`
+    inner: Option<W>,
+    /// Data waiting for the inner writer
+    buffer: Zeroizing<Vec<u8>>,
+    /// Bytes at the start of `buffer` already through the cipher
+    processed: usize
+}
+
+impl<C: StreamingCipher, W: Write, const ENCRYPT: bool> CipherWriter<C, W, ENCRYPT> {
+    pub fn new(cipher: C, inner: W) -> Self {
+        Self {
+            cipher,
+            inner: Some(inner),
+            buffer: Zeroizing::new(Vec::with_capacity(BUFFER_LEN)),
+            processed: 0
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Flushes the buffer, ends the message and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        self.cipher.end();
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// Passes the buffer through the cipher and hands it to the inner writer.
+    /// On error what was not written stays buffered, already processed
+    fn flush_buffer(&mut self) -> io::Result<()> {
+        apply::<C, ENCRYPT>(&mut self.cipher, &mut self.buffer[self.processed..])?;
+        self.processed = self.buffer.len();
+
+        let inner = self.inner.as_mut().unwrap();
+        while !self.buffer.is_empty() {
+            match inner.write(&self.buffer) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.buffer.drain(..n);
+                    self.processed -= n;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C: StreamingCipher, W: Write, const ENCRYPT: bool> Write for CipherWriter<C, W, ENCRYPT> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() == BUFFER_LEN {
+            self.flush_buffer()?;
+        }
+        let n = buf.len().min(BUFFER_LEN - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buffer()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<C: StreamingCipher, W: Write, const ENCRYPT: bool> Drop for CipherWriter<C, W, ENCRYPT> {
+    fn drop(&mut self) {
+        if self.inner.is_some() && self.flush_buffer().is_ok() {
+            self.cipher.end();
+        }
+    }
+}
+
+/// Reader passing what the inner reader yields through `C`, see the
+/// `EncryptReader` and `DecryptReader` aliases.
+///
+/// The message ends once the inner reader reaches EOF
+pub struct CipherReader<C: StreamingCipher, R: Read, const ENCRYPT: bool> {
+    cipher: C,
+    inner: R,
+    /// `BUFFER_LEN` bytes, processed ones from `pos` to `filled`
+    buffer: Zeroizing<Vec<u8>>,
+    pos: usize,
+    filled: usize,
+    /// Set once the message was ended
+    ended: bool
+}
+
+impl<C: StreamingCipher, R: Read, const ENCRYPT: bool> CipherReader<C, R, ENCRYPT> {
+    pub fn new(cipher: C, inner: R) -> Self {
+        Self {
+            cipher,
+            inner,
+            buffer: Zeroizing::new(vec![0u8; BUFFER_LEN]),
+            pos: 0,
+            filled: 0,
+            ended: false
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reads once from the inner reader, once the buffer is used up, and
+    /// passes what came through the cipher. Nothing is kept if that fails
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        self.pos    = 0;
+        self.filled = 0;
+        let n = loop {
+            match self.inner.read(&mut self.buffer) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e)
+            }
+        };
+
+        apply::<C, ENCRYPT>(&mut self.cipher, &mut self.buffer[..n])?;
+        self.filled = n;
+        Ok(())
+    }
+}
+
+impl<C: StreamingCipher, R: Read, const ENCRYPT: bool> Read for CipherReader<C, R, ENCRYPT> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos == self.filled {
+            self.fill_buffer()?;
+            if self.filled == 0 {
+                if !self.ended {
+                    self.cipher.end();
+                    self.ended = true;
+                }
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.filled - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, io::{ErrorKind, Read, Write}, rc::Rc};
+
+    use rand::{Rng, RngCore};
+
+    use crate::{ChaCha20, DChaCha20, Error};
+    use super::{DecryptReader, DecryptWriter, EncryptReader, EncryptWriter, StreamingCipher};
+
+    /// Writes `data` in random sized pieces, flushing now and then
+    fn write_randomly(writer: &mut impl Write, data: &[u8]) {
+        let mut rand = rand::thread_rng();
+        let mut data = data;
+        while !data.is_empty() {
+            let n = rand.gen_range(0..=data.len().min(10_000));
+            writer.write_all(&data[..n]).unwrap();
+            if rand.gen_bool(0.1) {
+                writer.flush().unwrap();
+            }
+            data = &data[n..];
+        }
+    }
+
+    /// Reads everything in random sized pieces
+    fn read_randomly(reader: &mut impl Read) -> Vec<u8> {
+        let mut rand = rand::thread_rng();
+        let mut data = Vec::new();
+        loop {
+            let mut buf = vec![0u8; rand.gen_range(1..10_000)];
+            match reader.read(&mut buf).unwrap() {
+                0 => return data,
+                n => data.extend_from_slice(&buf[..n])
+            }
+        }
+    }
+
+    #[test]
+    fn chacha20() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        let mut msg = vec![0u8; 100_000];
+        rand.fill_bytes(&mut msg);
+        let mut expected = msg.clone();
+        ChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        let mut writer = EncryptWriter::new(ChaCha20::new(&key, &nonce), Vec::new());
+        write_randomly(&mut writer, &msg);
+        assert_eq!(writer.finish().unwrap(), expected);
+
+        let mut reader = DecryptReader::new(ChaCha20::new(&key, &nonce), expected.as_slice());
+        assert_eq!(read_randomly(&mut reader), msg);
+
+        let mut reader = EncryptReader::new(ChaCha20::new(&key, &nonce), msg.as_slice());
+        assert_eq!(read_randomly(&mut reader), expected);
+
+        let mut writer = DecryptWriter::new(ChaCha20::new(&key, &nonce), Vec::new());
+        write_randomly(&mut writer, &expected);
+        assert_eq!(writer.finish().unwrap(), msg);
+    }
+
+    #[test]
+    fn dchacha20() {
+        let mut rand  = rand::rngs::OsRng {};
+        let mut key   = [0u8; 32];
+        rand.fill_bytes(&mut key);
+        let mut nonce = [0u8; 12];
+        rand.fill_bytes(&mut nonce);
+
+        for len in [0, 1, 63, 64, 65, 100_000, 100_001] {
+            let mut msg = vec![0u8; len];
+            rand.fill_bytes(&mut msg);
+            let mut expected = msg.clone();
+            let mut expected2 = b"next message".to_vec();
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            cipher.encrypt(&mut expected);
+            cipher.encrypt(&mut expected2);
+
+            let mut writer = EncryptWriter::new(DChaCha20::new(&key, &nonce), Vec::new());
+            write_randomly(&mut writer, &msg);
+            assert_eq!(writer.finish().unwrap(), expected);
+
+            let mut reader = DecryptReader::new(DChaCha20::new(&key, &nonce), expected.as_slice());
+            assert_eq!(read_randomly(&mut reader), msg);
+
+            let mut reader = EncryptReader::new(DChaCha20::new(&key, &nonce), msg.as_slice());
+            assert_eq!(read_randomly(&mut reader), expected);
+
+            let mut writer = DecryptWriter::new(DChaCha20::new(&key, &nonce), Vec::new());
+            write_randomly(&mut writer, &expected);
+            assert_eq!(writer.finish().unwrap(), msg);
+
+            // the final partial block is folded into the digest, the next
+            // message starts where an uninterrupted cipher would
+            let mut cipher = DChaCha20::new(&key, &nonce);
+            let mut reader = DecryptReader::new(&mut cipher, expected.as_slice());
+            assert_eq!(read_randomly(&mut reader), msg);
+            drop(reader);
+            cipher.decrypt(&mut expected2);
+            assert_eq!(expected2, b"next message");
+        }
+    }
+
+    #[test]
+    fn drop_flushes() {
+        let key = [1u8; 32];
+        let nonce = [2u8; 12];
+        let msg = b"hello".repeat(30);
+        let mut expected = msg.clone();
+        DChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        let mut output = Vec::new();
+        let mut writer = EncryptWriter::new(DChaCha20::new(&key, &nonce), &mut output);
+        writer.write_all(&msg).unwrap();
+        drop(writer);
+        assert_eq!(output, expected);
+
+        // a borrowed cipher is left at the end of the message, as by `finish`
+        let mut expected2 = b"next message".to_vec();
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.encrypt(&mut msg.clone());
+        cipher.encrypt(&mut expected2);
+
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        let mut writer = EncryptWriter::new(&mut cipher, Vec::new());
+        writer.write_all(&msg).unwrap();
+        drop(writer);
+        let mut next = b"next message".to_vec();
+        cipher.encrypt(&mut next);
+        assert_eq!(next, expected2);
+    }
+
+    /// Reader yielding the pieces pushed into it, `WouldBlock` when there are
+    /// none, as a non blocking socket would
+    #[derive(Clone, Default)]
+    struct Pipe(Rc<RefCell<VecDeque<Vec<u8>>>>);
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let mut pieces = self.0.borrow_mut();
+            let piece = pieces.front_mut().ok_or(ErrorKind::WouldBlock)?;
+            let n = buf.len().min(piece.len());
+            buf[..n].copy_from_slice(&piece[..n]);
+            piece.drain(..n);
+            if piece.is_empty() {
+                pieces.pop_front();
+            }
+            Ok(n)
+        }
+    }
+
+    /// Sends messages one flush at a time, each must be read before the next
+    /// one is sent
+    fn exchange<C: StreamingCipher>(new: impl Fn() -> C) {
+        let msgs: [&[u8]; 3] = [b"ping", b"pong, longer than a block so that it crosses into the next one", b"bye"];
+        let pipe = Pipe::default();
+        let mut writer = EncryptWriter::new(new(), Vec::new());
+        let mut reader = DecryptReader::new(new(), pipe.clone());
+        let mut buf = [0u8; 100];
+        for msg in msgs {
+            writer.write_all(msg).unwrap();
+            writer.flush().unwrap();
+            pipe.0.borrow_mut().push_back(std::mem::take(writer.get_mut()));
+
+            let n = reader.read(&mut buf).unwrap();
+            assert_eq!(&buf[..n], msg);
+            assert_eq!(reader.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        }
+    }
+
+    #[test]
+    fn partial_blocks() {
+        exchange(|| ChaCha20::new(&[1u8; 32], &[2u8; 12]));
+        exchange(|| DChaCha20::new(&[1u8; 32], &[2u8; 12]));
+    }
+
+    #[test]
+    fn limit() {
+        let mut writer = EncryptWriter::new(ChaCha20::new_with_counter(&[1u8; 32], &[2u8; 12], u32::MAX), Vec::new());
+        writer.write_all(&[0u8; 65]).unwrap();
+        let err = writer.flush().unwrap_err();
+        assert_eq!(err.into_inner().unwrap().downcast_ref::<Error>(), Some(&Error::LimitExceeded));
+    }
+}
@@ -0,0 +1,196 @@
+//! Password based key derivation for containers, enabled by the `password`
+//! feature.
+
+use zeroize::Zeroizing;
+
+use crate::error::Error;
+
+/// Length of the salt stored in a password protected container
+pub const SALT_LEN: usize = 16;
+
+/// Memory hard function deriving a container key from a password, with its
+/// cost parameters.
+///
+/// Parameters below `min` are refused both when writing and when reading a
+/// container, so that a forged header can't downgrade the derivation; those
+/// above `max` are refused as well, so that it can't make the reader spend
+/// more than 1 GiB of memory and a handful of passes over it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// Argon2id (RFC 9106), memory in KiB, passes and lanes
+    Argon2id { m_cost: u32, t_cost: u32, p_cost: u32 },
+    /// scrypt (RFC 7914), N = 2^log_n, block size and parallelism
+    Scrypt { log_n: u8, r: u32, p: u32 }
+}
+
+impl Default for Kdf {
+    /// Argon2id with the second recommended option of RFC 9106: 64 MiB, 3 passes, 4 lanes
+    fn default() -> Self {
+        Kdf::Argon2id { m_cost: 64 * 1024, t_cost: 3, p_cost: 4 }
+    }
+}
+
+impl Kdf {
+    /// scrypt with N = 2^17, r = 8, p = 1
+    pub const SCRYPT: Self = Kdf::Scrypt { log_n: 17, r: 8, p: 1 };
+
+    /// Weakest parameters accepted for this function
+    pub fn min(&self) -> Self {
+        match self {
+            Kdf::Argon2id { .. } => Kdf::Argon2id { m_cost: 19 * 1024, t_cost: 2, p_cost: 1 },
+            Kdf::Scrypt { .. } => Kdf::Scrypt { log_n: 15, r: 8, p: 1 }
+        }
+    }
+
+    /// Strongest parameters accepted for this function: 1 GiB and 8 passes
+    /// for Argon2id, 128 * r * N = 1 GiB and 4 times over it for scrypt
+    pub fn max(&self) -> Self {
+        match self {
+            Kdf::Argon2id { .. } => Kdf::Argon2id { m_cost: 1024 * 1024, t_cost: 8, p_cost: 16 },
+            Kdf::Scrypt { .. } => Kdf::Scrypt { log_n: 20, r: 8, p: 4 }
+        }
+    }
+
+    /// Parameters as `[u32; 3]`, in the order of the variant fields
+    fn params(&self) -> [u32; 3] {
+        match *self {
+            Kdf::Argon2id { m_cost, t_cost, p_cost } => [m_cost, t_cost, p_cost],
+            Kdf::Scrypt { log_n, r, p } => [log_n as u32, r, p]
+        }
+    }
+
+    /// Returns `Error::WeakKdf` below `min`, `Error::InvalidKdf` above `max`
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        let params = self.params();
+        if params.iter().zip(self.min().params()).any(|(p, min)| *p < min) {
+            return Err(Error::WeakKdf);
+        }
+        if params.iter().zip(self.max().params()).any(|(p, max)| *p > max) {
+            return Err(Error::InvalidKdf);
+        }
+        Ok(())
+    }
+
+    /// Identifier and parameters, as stored in a container header
+    pub(crate) fn to_bytes(self) -> [u8; 13] {
+        let mut bytes = [0u8; 13];
+        bytes[0] = match self {
+            Kdf::Argon2id { .. } => 1,
+            Kdf::Scrypt { .. } => 2
+        };
+        for (chunk, param) in bytes[1..].chunks_exact_mut(4).zip(self.params()) {
+            chunk.copy_from_slice(&param.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Parses `to_bytes`, `None` for an unknown identifier or a `log_n` not
+    /// fitting a byte
+    pub(crate) fn from_bytes(bytes: &[u8; 13]) -> Option<Self> {
+        let param = |i: usize| u32::from_le_bytes(bytes[1 + 4 * i..5 + 4 * i].try_into().unwrap());
+        match bytes[0] {
+            1 => Some(Kdf::Argon2id { m_cost: param(0), t_cost: param(1), p_cost: param(2) }),
+            2 => Some(Kdf::Scrypt { log_n: u8::try_from(param(0)).ok()?, r: param(1), p: param(2) }),
+            _ => None
+        }
+    }
+
+    /// Derives a 32 bytes key from `password` and `salt`.
+    /// Returns `Error::WeakKdf` if the parameters are below `min`,
+    /// `Error::InvalidKdf` if they are above `max`
+    pub fn derive(&self, password: &[u8], salt: &[u8; SALT_LEN]) -> Result<Zeroizing<[u8; 32]>, Error> {
+        self.check()?;
+        self.derive_unchecked(password, salt)
+    }
+
+    fn derive_unchecked(&self, password: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, Error> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        match *self {
+            Kdf::Argon2id { m_cost, t_cost, p_cost } => {
+                argon2id(m_cost, t_cost, p_cost, &[], &[])?
+                    .hash_password_into(password, salt, key.as_mut_slice())
+                    .map_err(|_| Error::InvalidKdf)?;
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(log_n, r, p, 32).map_err(|_| Error::InvalidKdf)?;
+                scrypt::scrypt(password, salt, &params, key.as_mut_slice()).map_err(|_| Error::InvalidKdf)?;
+            }
+        }
+        Ok(key)
+    }
+}
+
+/// Argon2id version 1.3 with a 32 bytes output, along with the secret and
+/// associated data of RFC 9106, which containers leave empty
+fn argon2id<'a>(m_cost: u32, t_cost: u32, p_cost: u32, secret: &'a [u8], data: &[u8]) -> Result<argon2::Argon2<'a>, Error> {
+    let data = argon2::AssociatedData::new(data).map_err(|_| Error::InvalidKdf)?;
+    let params = argon2::ParamsBuilder::new()
+        .m_cost(m_cost)
+        .t_cost(t_cost)
+        .p_cost(p_cost)
+        .output_len(32)
+        .data(data)
+        .build()
+        .map_err(|_| Error::InvalidKdf)?;
+    argon2::Argon2::new_with_secret(secret, argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .map_err(|_| Error::InvalidKdf)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use crate::Error;
+    use super::{argon2id, Kdf};
+
+    #[test]
+    fn scrypt_vectors() {
+        // RFC 7914 section 12, first half of the 64 bytes output
+        let kdf = Kdf::Scrypt { log_n: 10, r: 8, p: 16 };
+        let key = kdf.derive_unchecked(b"password", b"NaCl").unwrap();
+        assert_eq!(*key, hex!("fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162"));
+
+        // Python's hashlib.scrypt
+        let key = Kdf::SCRYPT.min().derive(b"password", &hex!("000102030405060708090a0b0c0d0e0f")).unwrap();
+        assert_eq!(*key, hex!("2bf82378f6c4c5f8c13d9eb323452f9e37a06291fc00b2f5afae8fcad22f33e3"));
+    }
+
+    #[test]
+    fn argon2id_vectors() {
+        // RFC 9106 section 5.3: 32 KiB, 3 passes, 4 lanes
+        let mut tag = [0u8; 32];
+        argon2id(32, 3, 4, &[3; 8], &[4; 12]).unwrap().hash_password_into(&[1; 32], &[2; 16], &mut tag).unwrap();
+        assert_eq!(tag, hex!("0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659"));
+
+        // computed with an implementation of RFC 9106 independent of the
+        // argon2 crate, checked against the vector above
+        let kdf = Kdf::Argon2id { m_cost: 19 * 1024, t_cost: 2, p_cost: 1 };
+        let key = kdf.derive(b"password", &hex!("000102030405060708090a0b0c0d0e0f")).unwrap();
+        assert_eq!(*key, hex!("39903a34cd393876436e671cd690dfe8ab9ce7b13459a7e04ec0e4f0694ddb0d"));
+    }
+
+    #[test]
+    fn limits() {
+        for kdf in [Kdf::default(), Kdf::SCRYPT] {
+            assert_eq!(kdf.check(), Ok(()));
+            assert_eq!(kdf.min().check(), Ok(()));
+            assert_eq!(kdf.max().check(), Ok(()));
+            assert_eq!(Kdf::from_bytes(&kdf.to_bytes()), Some(kdf));
+        }
+
+        assert_eq!(Kdf::Argon2id { m_cost: 8, t_cost: 3, p_cost: 1 }.derive(b"", &[0; 16]).err(), Some(Error::WeakKdf));
+        assert_eq!(Kdf::Argon2id { m_cost: 65536, t_cost: 1, p_cost: 1 }.check(), Err(Error::WeakKdf));
+        assert_eq!(Kdf::Scrypt { log_n: 10, r: 8, p: 1 }.check(), Err(Error::WeakKdf));
+        assert_eq!(Kdf::Scrypt { log_n: 30, r: 8, p: 1 }.check(), Err(Error::InvalidKdf));
+        assert_eq!(Kdf::Scrypt { log_n: 20, r: 32, p: 1 }.check(), Err(Error::InvalidKdf));
+        assert_eq!(Kdf::Argon2id { m_cost: u32::MAX, t_cost: 3, p_cost: 1 }.check(), Err(Error::InvalidKdf));
+        assert_eq!(Kdf::Argon2id { m_cost: 64 * 1024, t_cost: 64, p_cost: 1 }.derive(b"", &[0; 16]).err(), Some(Error::InvalidKdf));
+
+        let mut bytes = Kdf::SCRYPT.to_bytes();
+        bytes[2] = 1;
+        assert_eq!(Kdf::from_bytes(&bytes), None);
+        bytes[0] = 3;
+        assert_eq!(Kdf::from_bytes(&bytes), None);
+    }
+}
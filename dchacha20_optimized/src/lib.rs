@@ -1,7 +1,42 @@
-#![feature(portable_simd)]
-#![feature(slice_pattern)]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![cfg_attr(feature = "nightly-simd", feature(portable_simd))]
+#[cfg(feature = "tokio")]
+mod async_io;
+mod backend;
 mod chacha20;
+mod chacha20poly1305;
+#[cfg(feature = "std")]
+mod container;
 mod dchacha20;
+mod dchacha20aead;
+mod error;
+#[cfg(feature = "std")]
+mod io;
+#[cfg(feature = "password")]
+mod kdf;
+mod poly1305;
+mod simd;
+mod state;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "cipher")]
+mod traits;
 
-pub use chacha20::ChaCha20;
-pub use dchacha20::DChaCha20;
+#[cfg(feature = "tokio")]
+pub use async_io::{AsyncCipherReader, AsyncCipherWriter, AsyncDecryptReader, AsyncDecryptWriter, AsyncEncryptReader, AsyncEncryptWriter};
+pub use backend::Backend;
+pub use chacha20::{hchacha20, ChaCha, ChaCha8, ChaCha12, ChaCha20, ChaCha20Legacy};
+pub use chacha20poly1305::ChaCha20Poly1305;
+#[cfg(feature = "std")]
+pub use container::{Algorithm, ContainerReader, ContainerWriter, MAX_CHUNK_SIZE, NONCE_LEN};
+pub use dchacha20::{DChaCha, DChaCha8, DChaCha12, DChaCha20, DChaCha20Legacy, Preceding};
+pub use dchacha20aead::DChaCha20Aead;
+pub use error::Error;
+#[cfg(feature = "password")]
+pub use kdf::{Kdf, SALT_LEN};
+#[cfg(feature = "std")]
+pub use io::{CipherReader, CipherWriter, DecryptReader, DecryptWriter, EncryptReader, EncryptWriter, StreamingCipher};
+pub use state::CipherState;
+
+#[cfg(feature = "cipher")]
+pub use cipher;
@@ -0,0 +1,280 @@
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Poly1305 one-time authenticator (RFC 8439 section 2.5)
+///
+/// The accumulator and `r` are kept in five 26-bit limbs so every
+/// multiplication fits in a u64 without carries.
+pub(crate) struct Poly1305 {
+    /// Clamped `r` part of the key
+    r: [u32; 5],
+    /// Accumulator
+    h: [u32; 5],
+    /// `s` part of the key, added at the end
+    pad: [u32; 4],
+    /// Bytes that did not fill a whole 16 bytes block yet
+    buffer: [u8; 16],
+    leftover: usize
+}
+
+const MASK: u32 = 0x3ffffff;
+
+impl Poly1305 {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            r: [
+                 u32_from_le_bytes(&key[0..4])        & 0x3ffffff,
+                (u32_from_le_bytes(&key[3..7])  >> 2) & 0x3ffff03,
+                (u32_from_le_bytes(&key[6..10]) >> 4) & 0x3ffc0ff,
+                (u32_from_le_bytes(&key[9..13]) >> 6) & 0x3f03fff,
+                (u32_from_le_bytes(&key[12..16]) >> 8) & 0x00fffff,
+            ],
+            h: [0u32; 5],
+            pad: [
+                u32_from_le_bytes(&key[16..20]), u32_from_le_bytes(&key[20..24]),
+                u32_from_le_bytes(&key[24..28]), u32_from_le_bytes(&key[28..32]),
+            ],
+            buffer: [0u8; 16],
+            leftover: 0
+        }
+    }
+
+    /// Process one 16 bytes block, `hibit` is the 2^128 bit set for full blocks
+    #[inline(always)]
+    fn block(&mut self, m: &[u8; 16], hibit: u32) {
+        let [r0, r1, r2, r3, r4] = self.r;
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+        let [mut h0, mut h1, mut h2, mut h3, mut h4] = self.h;
+
+        h0 +=  u32_from_le_bytes(&m[0..4])        & MASK;
+        h1 += (u32_from_le_bytes(&m[3..7])  >> 2) & MASK;
+        h2 += (u32_from_le_bytes(&m[6..10]) >> 4) & MASK;
+        h3 += (u32_from_le_bytes(&m[9..13]) >> 6) & MASK;
+        h4 += (u32_from_le_bytes(&m[12..16]) >> 8) | hibit;
+
+        let mul = |a: u32, b: u32| a as u64 * b as u64;
+        let d0 = mul(h0, r0) + mul(h1, s4) + mul(h2, s3) + mul(h3, s2) + mul(h4, s1);
+        let mut d1 = mul(h0, r1) + mul(h1, r0) + mul(h2, s4) + mul(h3, s3) + mul(h4, s2);
+        let mut d2 = mul(h0, r2) + mul(h1, r1) + mul(h2, r0) + mul(h3, s4) + mul(h4, s3);
+        let mut d3 = mul(h0, r3) + mul(h1, r2) + mul(h2, r1) + mul(h3, r0) + mul(h4, s4);
+        let mut d4 = mul(h0, r4) + mul(h1, r3) + mul(h2, r2) + mul(h3, r1) + mul(h4, r0);
+
+        // partial reduction mod 2^130 - 5
+        let mut c = d0 >> 26;
+        h0 = d0 as u32 & MASK;
+        d1 += c; c = d1 >> 26; h1 = d1 as u32 & MASK;
+        d2 += c; c = d2 >> 26; h2 = d2 as u32 & MASK;
+        d3 += c; c = d3 >> 26; h3 = d3 as u32 & MASK;
+        d4 += c; c = d4 >> 26; h4 = d4 as u32 & MASK;
+        h0 += c as u32 * 5;
+        h1 += h0 >> 26;
+        h0 &= MASK;
+
+        self.h = [h0, h1, h2, h3, h4];
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        if self.leftover > 0 {
+            let take = (16 - self.leftover).min(data.len());
+            self.buffer[self.leftover..self.leftover + take].copy_from_slice(&data[..take]);
+            self.leftover += take;
+            data = &data[take..];
+            if self.leftover < 16 {
+                return;
+            }
+            let block = self.buffer;
+            self.block(&block, 1 << 24);
+            self.leftover = 0;
+        }
+
+        let mut chunks = data.chunks_exact(16);
+        for chunk in &mut chunks {
+            self.block(chunk.try_into().unwrap(), 1 << 24);
+        }
+
+        let rem = chunks.remainder();
+        self.buffer[..rem.len()].copy_from_slice(rem);
+        self.leftover = rem.len();
+    }
+
+    /// Zero pad the pending input to a 16 bytes boundary, as the AEAD
+    /// construction requires between aad and ciphertext
+    pub fn pad(&mut self) {
+        if self.leftover > 0 {
+            self.buffer[self.leftover..].fill(0);
+            let block = self.buffer;
+            self.block(&block, 1 << 24);
+            self.leftover = 0;
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; 16] {
+        if self.leftover > 0 {
+            self.buffer[self.leftover] = 1;
+            self.buffer[self.leftover + 1..].fill(0);
+            let block = self.buffer;
+            self.block(&block, 0);
+        }
+
+        // full carry
+        let [mut h0, mut h1, mut h2, mut h3, mut h4] = self.h;
+        let mut c;
+        c = h1 >> 26; h1 &= MASK;
+        h2 += c; c = h2 >> 26; h2 &= MASK;
+        h3 += c; c = h3 >> 26; h3 &= MASK;
+        h4 += c; c = h4 >> 26; h4 &= MASK;
+        h0 += c * 5; c = h0 >> 26; h0 &= MASK;
+        h1 += c;
+
+        // g = h + -p
+        let mut g0 = h0.wrapping_add(5); c = g0 >> 26; g0 &= MASK;
+        let mut g1 = h1.wrapping_add(c); c = g1 >> 26; g1 &= MASK;
+        let mut g2 = h2.wrapping_add(c); c = g2 >> 26; g2 &= MASK;
+        let mut g3 = h3.wrapping_add(c); c = g3 >> 26; g3 &= MASK;
+        let mut g4 = h4.wrapping_add(c).wrapping_sub(1 << 26);
+
+        // select h if h < p, or g otherwise, without branching
+        let mut mask = (g4 >> 31).wrapping_sub(1);
+        g0 &= mask; g1 &= mask; g2 &= mask; g3 &= mask; g4 &= mask;
+        mask = !mask;
+        h0 = (h0 & mask) | g0;
+        h1 = (h1 & mask) | g1;
+        h2 = (h2 & mask) | g2;
+        h3 = (h3 & mask) | g3;
+        h4 = (h4 & mask) | g4;
+
+        // h = h % 2^128
+        let h = [
+            h0 | (h1 << 26),
+            (h1 >> 6) | (h2 << 20),
+            (h2 >> 12) | (h3 << 14),
+            (h3 >> 18) | (h4 << 8),
+        ];
+
+        // tag = (h + s) % 2^128
+        let mut tag = [0u8; 16];
+        let mut f = 0u64;
+        for (i, word) in h.iter().enumerate() {
+            f = *word as u64 + self.pad[i] as u64 + (f >> 32);
+            tag[i * 4..i * 4 + 4].copy_from_slice(&(f as u32).to_le_bytes());
+        }
+        tag
+    }
+}
+
+
+impl Zeroize for Poly1305 {
+    fn zeroize(&mut self) {
+        self.r.zeroize();
+        self.h.zeroize();
+        self.pad.zeroize();
+        self.buffer.zeroize();
+        self.leftover.zeroize();
+    }
+}
+
+impl Drop for Poly1305 {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for Poly1305 {}
+
+
+/// Method to convert to u32
+/// This should never panic since we are sure of size we pass to method
+fn u32_from_le_bytes(slice: &[u8]) -> u32 {
+    u32::from_le_bytes(
+        slice
+            .try_into()
+            .expect("u32_from_le_bytes given u8 slice with invalid size")
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use hex_literal::hex;
+
+    use crate::test_util::{contains, field, fields_after_drop};
+    use super::Poly1305;
+
+    fn mac(key: &[u8; 32], msg: &[u8]) -> [u8; 16] {
+        let mut poly = Poly1305::new(key);
+        poly.update(msg);
+        poly.finalize()
+    }
+
+    #[test]
+    fn validate() {
+        // RFC 8439 section 2.5.2
+        let key = hex!("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b");
+        let msg = b"Cryptographic Forum Research Group";
+        assert_eq!(mac(&key, msg), hex!("a8061dc1305136c6c22b8baf0c0127a9"));
+
+        // Same message fed in uneven pieces
+        let mut poly = Poly1305::new(&key);
+        for chunk in msg.chunks(7) {
+            poly.update(chunk);
+        }
+        assert_eq!(poly.finalize(), hex!("a8061dc1305136c6c22b8baf0c0127a9"));
+    }
+
+    #[test]
+    fn rfc8439_appendix_a3() {
+        let ff = [0xffu8; 16];
+
+        // #1
+        assert_eq!(mac(&[0u8; 32], &[0u8; 64]), [0u8; 16]);
+
+        // #5
+        let key = hex!("02000000000000000000000000000000 00000000000000000000000000000000");
+        assert_eq!(mac(&key, &ff), hex!("03000000000000000000000000000000"));
+
+        // #6
+        let key = hex!("02000000000000000000000000000000 ffffffffffffffffffffffffffffffff");
+        assert_eq!(mac(&key, &hex!("02000000000000000000000000000000")), hex!("03000000000000000000000000000000"));
+
+        // #7
+        let key = hex!("01000000000000000000000000000000 00000000000000000000000000000000");
+        let msg = hex!("ffffffffffffffffffffffffffffffff f0ffffffffffffffffffffffffffffff 11000000000000000000000000000000");
+        assert_eq!(mac(&key, &msg), hex!("05000000000000000000000000000000"));
+
+        // #8
+        let msg = hex!("ffffffffffffffffffffffffffffffff fbfefefefefefefefefefefefefefefe 01010101010101010101010101010101");
+        assert_eq!(mac(&key, &msg), [0u8; 16]);
+
+        // #9
+        let key = hex!("02000000000000000000000000000000 00000000000000000000000000000000");
+        assert_eq!(mac(&key, &hex!("fdffffffffffffffffffffffffffffff")), hex!("faffffffffffffffffffffffffffffff"));
+
+        // #10 and #11
+        let key = hex!("01000000000000000400000000000000 00000000000000000000000000000000");
+        let msg = hex!("
+            e33594d7505e43b90000000000000000 3394d7505e4379cd0100000000000000
+            00000000000000000000000000000000 01000000000000000000000000000000
+        ");
+        assert_eq!(mac(&key, &msg), hex!("14000000000000005500000000000000"));
+        assert_eq!(mac(&key, &msg[..48]), hex!("13000000000000000000000000000000"));
+    }
+
+    #[test]
+    fn zeroize_on_drop() {
+        let key = [0xa5u8; 32];
+        let pad = [0xa5a5a5a5u32; 4].map(u32::to_le_bytes).concat();
+        let msg = [0x5au8; 10];
+
+        // leaves the message buffered, short of a block
+        let (live, dropped) = fields_after_drop(Poly1305::new(&key), |poly| poly.update(&msg), |poly| {
+            // SAFETY: only computes field addresses
+            unsafe { vec![field(ptr::addr_of!((*poly).pad)), field(ptr::addr_of!((*poly).buffer))] }
+        });
+
+        for secret in [&pad[..], &msg[..]] {
+            assert!(contains(&live, secret));
+            assert!(!contains(&dropped, secret));
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Vector types used by the ciphers.
+//!
+//! With the `nightly-simd` feature these are the `std::simd` types. Otherwise
+//! a stand-in with the same API is used so the crate builds on stable. Its
+//! lanes are plain arrays; on x86_64 the u32 operations go through SSE2/AVX2
+//! intrinsics, elsewhere they are scalar loops left to LLVM to vectorize.
+
+#[cfg(feature = "nightly-simd")]
+pub(crate) use core::simd::{u32x16, u32x4, u32x8, u8x64, ToBytes};
+
+#[cfg(not(feature = "nightly-simd"))]
+pub(crate) use scalar::{u32x16, u32x4, u32x8, u8x64, ToBytes};
+
+use core::ops::{AddAssign, BitOr, BitXorAssign, Shl, Shr};
+
+/// u32 vector with what a ChaCha quarter round needs, so the same round
+/// code runs on the rows of one block or on words of several blocks side by side
+pub(crate) trait Lanes:
+    Copy + AddAssign + BitXorAssign + BitOr<Output = Self> + Shl<u32, Output = Self> + Shr<u32, Output = Self> {}
+
+impl<T> Lanes for T where
+    T: Copy + AddAssign + BitXorAssign + BitOr<Output = T> + Shl<u32, Output = T> + Shr<u32, Output = T> {}
+
+#[cfg(not(feature = "nightly-simd"))]
+mod scalar {
+    use core::{
+        ops::{AddAssign, BitOr, BitXor, BitXorAssign, Index, IndexMut, Shl, Shr},
+        slice::SliceIndex
+    };
+
+    /// Fixed size group of `N` lanes, mirroring the parts of `std::simd::Simd`
+    /// the ciphers rely on
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct Simd<T, const N: usize>([T; N]);
+
+    #[allow(non_camel_case_types)]
+    pub type u32x4 = Simd<u32, 4>;
+    #[allow(non_camel_case_types)]
+    pub type u32x8 = Simd<u32, 8>;
+    #[allow(non_camel_case_types)]
+    pub type u32x16 = Simd<u32, 16>;
+    #[allow(non_camel_case_types)]
+    pub type u8x64 = Simd<u8, 64>;
+
+    impl<T: Copy, const N: usize> Simd<T, N> {
+        #[inline(always)]
+        pub const fn from_array(array: [T; N]) -> Self {
+            Self(array)
+        }
+
+        /// # Panics
+        /// If `slice` is shorter than `N`
+        #[inline(always)]
+        pub fn from_slice(slice: &[T]) -> Self {
+            Self(slice[..N].try_into().unwrap())
+        }
+
+        /// # Panics
+        /// If `slice` is shorter than `N`
+        #[inline(always)]
+        pub fn copy_to_slice(self, slice: &mut [T]) {
+            slice[..N].copy_from_slice(&self.0);
+        }
+
+        #[inline(always)]
+        pub const fn to_array(self) -> [T; N] {
+            self.0
+        }
+
+        #[inline(always)]
+        pub const fn as_array(&self) -> &[T; N] {
+            &self.0
+        }
+
+        #[inline(always)]
+        pub fn as_mut_array(&mut self) -> &mut [T; N] {
+            &mut self.0
+        }
+    }
+
+    impl<T, const N: usize> From<[T; N]> for Simd<T, N> {
+        #[inline(always)]
+        fn from(array: [T; N]) -> Self {
+            Self(array)
+        }
+    }
+
+    impl<T, const N: usize> From<Simd<T, N>> for [T; N] {
+        #[inline(always)]
+        fn from(vector: Simd<T, N>) -> Self {
+            vector.0
+        }
+    }
+
+    impl<T, I: SliceIndex<[T]>, const N: usize> Index<I> for Simd<T, N> {
+        type Output = I::Output;
+
+        #[inline(always)]
+        fn index(&self, index: I) -> &Self::Output {
+            &self.0[index]
+        }
+    }
+
+    impl<T, I: SliceIndex<[T]>, const N: usize> IndexMut<I> for Simd<T, N> {
+        #[inline(always)]
+        fn index_mut(&mut self, index: I) -> &mut Self::Output {
+            &mut self.0[index]
+        }
+    }
+
+    /// Lane-wise operators, arithmetic wraps like it does in `std::simd`
+    macro_rules! lanewise {
+        ($($t:ty)*) => {$(
+            impl<const N: usize> BitXor for Simd<$t, N> {
+                type Output = Self;
+
+                #[inline(always)]
+                fn bitxor(mut self, rhs: Self) -> Self {
+                    self ^= rhs;
+                    self
+                }
+            }
+
+            impl<const N: usize> BitXorAssign for Simd<$t, N> {
+                #[inline(always)]
+                fn bitxor_assign(&mut self, rhs: Self) {
+                    for (a, b) in self.0.iter_mut().zip(rhs.0) {
+                        *a ^= b;
+                    }
+                }
+            }
+
+            impl<const N: usize> BitOr for Simd<$t, N> {
+                type Output = Self;
+
+                #[inline(always)]
+                fn bitor(mut self, rhs: Self) -> Self {
+                    for (a, b) in self.0.iter_mut().zip(rhs.0) {
+                        *a |= b;
+                    }
+                    self
+                }
+            }
+
+            impl<const N: usize> AddAssign for Simd<$t, N> {
+                #[inline(always)]
+                fn add_assign(&mut self, rhs: Self) {
+                    for (a, b) in self.0.iter_mut().zip(rhs.0) {
+                        *a = a.wrapping_add(b);
+                    }
+                }
+            }
+
+            impl<const N: usize> AddAssign<&Self> for Simd<$t, N> {
+                #[inline(always)]
+                fn add_assign(&mut self, rhs: &Self) {
+                    *self += *rhs;
+                }
+            }
+
+            impl<const N: usize> Shl<$t> for Simd<$t, N> {
+                type Output = Self;
+
+                #[inline(always)]
+                fn shl(mut self, rhs: $t) -> Self {
+                    for a in &mut self.0 {
+                        *a <<= rhs;
+                    }
+                    self
+                }
+            }
+
+            impl<const N: usize> Shr<$t> for Simd<$t, N> {
+                type Output = Self;
+
+                #[inline(always)]
+                fn shr(mut self, rhs: $t) -> Self {
+                    for a in &mut self.0 {
+                        *a >>= rhs;
+                    }
+                    self
+                }
+            }
+        )*};
+    }
+
+    lanewise! { u8 }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    lanewise! { u32 }
+
+    /// u32 lanes go through SSE2 4 at a time, as every x86_64 CPU has it, or
+    /// AVX2 8 at a time when the build enables it.
+    /// LLVM does not vectorize the rotations of the rounds on its own, which
+    /// leaves the multi-block keystream slower than the single block one
+    #[cfg(target_arch = "x86_64")]
+    mod x86 {
+        use core::{
+            arch::x86_64::*,
+            ops::{AddAssign, BitOr, BitXor, BitXorAssign, Shl, Shr}
+        };
+
+        use super::Simd;
+
+        /// Implements a u32 lane-wise operator on top of the matching SSE2 and
+        /// AVX2 intrinsics, `$a` and `$b` being the lanes of both operands
+        macro_rules! x86_op {
+            ($lhs:expr, $rhs:expr, |$a:ident, $b:ident| $sse2:expr, $avx2:expr) => {{
+                let (lhs, rhs): (&mut [u32; N], &[u32; N]) = ($lhs, $rhs);
+                const { assert!(N.is_multiple_of(4)) };
+                // SAFETY: loads and stores are unaligned and stay within the
+                // chunks, the intrinsics are all in the target baseline
+                unsafe {
+                    if cfg!(target_feature = "avx2") && N.is_multiple_of(8) {
+                        for (l, r) in lhs.chunks_exact_mut(8).zip(rhs.chunks_exact(8)) {
+                            let $a = _mm256_loadu_si256(l.as_ptr().cast());
+                            let $b = _mm256_loadu_si256(r.as_ptr().cast());
+                            _mm256_storeu_si256(l.as_mut_ptr().cast(), $avx2);
+                        }
+                    } else {
+                        for (l, r) in lhs.chunks_exact_mut(4).zip(rhs.chunks_exact(4)) {
+                            let $a = _mm_loadu_si128(l.as_ptr().cast());
+                            let $b = _mm_loadu_si128(r.as_ptr().cast());
+                            _mm_storeu_si128(l.as_mut_ptr().cast(), $sse2);
+                        }
+                    }
+                }
+            }};
+        }
+
+        impl<const N: usize> BitXor for Simd<u32, N> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn bitxor(mut self, rhs: Self) -> Self {
+                self ^= rhs;
+                self
+            }
+        }
+
+        impl<const N: usize> BitXorAssign for Simd<u32, N> {
+            #[inline(always)]
+            fn bitxor_assign(&mut self, rhs: Self) {
+                x86_op!(&mut self.0, &rhs.0, |a, b| _mm_xor_si128(a, b), _mm256_xor_si256(a, b));
+            }
+        }
+
+        impl<const N: usize> BitOr for Simd<u32, N> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn bitor(mut self, rhs: Self) -> Self {
+                x86_op!(&mut self.0, &rhs.0, |a, b| _mm_or_si128(a, b), _mm256_or_si256(a, b));
+                self
+            }
+        }
+
+        impl<const N: usize> AddAssign for Simd<u32, N> {
+            #[inline(always)]
+            fn add_assign(&mut self, rhs: Self) {
+                x86_op!(&mut self.0, &rhs.0, |a, b| _mm_add_epi32(a, b), _mm256_add_epi32(a, b));
+            }
+        }
+
+        impl<const N: usize> AddAssign<&Self> for Simd<u32, N> {
+            #[inline(always)]
+            fn add_assign(&mut self, rhs: &Self) {
+                *self += *rhs;
+            }
+        }
+
+        impl<const N: usize> Shl<u32> for Simd<u32, N> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn shl(mut self, rhs: u32) -> Self {
+                let lanes = self.0;
+                // SAFETY: SSE2 is part of the x86_64 baseline
+                let count = unsafe { _mm_cvtsi32_si128(rhs as i32) };
+                x86_op!(&mut self.0, &lanes, |a, _b| _mm_sll_epi32(a, count), _mm256_sll_epi32(a, count));
+                self
+            }
+        }
+
+        impl<const N: usize> Shr<u32> for Simd<u32, N> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn shr(mut self, rhs: u32) -> Self {
+                let lanes = self.0;
+                // SAFETY: SSE2 is part of the x86_64 baseline
+                let count = unsafe { _mm_cvtsi32_si128(rhs as i32) };
+                x86_op!(&mut self.0, &lanes, |a, _b| _mm_srl_epi32(a, count), _mm256_srl_epi32(a, count));
+                self
+            }
+        }
+    }
+
+    /// Byte view of a vector, as `std::simd::ToBytes`
+    pub trait ToBytes {
+        type Bytes;
+
+        fn to_le_bytes(self) -> Self::Bytes;
+    }
+
+    impl ToBytes for u32x16 {
+        type Bytes = u8x64;
+
+        #[inline(always)]
+        fn to_le_bytes(self) -> u8x64 {
+            let mut bytes = [0u8; 64];
+            for (chunk, word) in bytes.chunks_exact_mut(4).zip(self.0) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+            Simd(bytes)
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{chacha20poly1305::ChaCha20Poly1305, error::Error};
+
+/// Version of the `CipherState` layout
+const VERSION: u8 = 1;
+
+/// Associated data of sealed states, keeps them apart from other messages
+/// encrypted under the same wrapping key
+const SEALED_AAD: &[u8] = b"dchacha20 cipher state";
+
+/// Which cipher a `CipherState` was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum Kind {
+    ChaCha = 1,
+    DChaCha = 2
+}
+
+/// Snapshot of a `ChaCha` or `DChaCha` taken with `export_state`, to resume
+/// it later with `from_state`, e.g. after a restart.
+///
+/// The layout is versioned, all integers little endian:
+///
+/// ```text
+/// offset  len  field
+///      0    1  format version, 1
+///      1    1  cipher, 1 = ChaCha, 2 = DChaCha
+///      2    2  rounds
+///      4    1  1 for the legacy 64 bit counter layout, 0 otherwise
+///      5   64  state words: constants, key, counter of the next block, nonce
+///     69    1  bytes of the current block already used
+///     70    1  1 once the counter went past its last value
+///     71   64  DChaCha digest
+///    135   64  DChaCha ciphertext of the current block
+///    199   32  DChaCha xorshift state
+///    231    1  DChaCha xorshift seed length
+/// ```
+///
+/// The DChaCha fields are zero for ChaCha.
+/// The state holds the key: keep it as secret as the key itself, or `seal` it.
+/// The backend is not part of it, a resumed cipher picks the one of the CPU it
+/// runs on
+pub struct CipherState {
+    bytes: [u8; CipherState::LEN]
+}
+
+/// Fields of a `CipherState`, as the ciphers see them
+pub(crate) struct Snapshot {
+    pub(crate) state: [u32; 16],
+    pub(crate) offset: usize,
+    pub(crate) exhausted: bool,
+    pub(crate) prev_dig: [u8; 64],
+    pub(crate) partial: [u8; 64],
+    pub(crate) xorshift: [u32; 8],
+    pub(crate) xorshift_len: usize
+}
+
+impl CipherState {
+    /// Length of a serialized state
+    pub const LEN: usize = 232;
+    /// Length of a sealed state: nonce, encrypted state and tag
+    pub const SEALED_LEN: usize = 12 + Self::LEN + 16;
+
+    pub(crate) fn new(kind: Kind, rounds: usize, legacy: bool, snapshot: &Snapshot) -> Self {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0] = VERSION;
+        bytes[1] = kind as u8;
+        bytes[2..4].copy_from_slice(&(rounds as u16).to_le_bytes());
+        bytes[4] = legacy as u8;
+        for (chunk, word) in bytes[5..69].chunks_exact_mut(4).zip(snapshot.state) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes[69] = snapshot.offset as u8;
+        bytes[70] = snapshot.exhausted as u8;
+        bytes[71..135].copy_from_slice(&snapshot.prev_dig);
+        bytes[135..199].copy_from_slice(&snapshot.partial);
+        for (chunk, word) in bytes[199..231].chunks_exact_mut(4).zip(snapshot.xorshift) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes[231] = snapshot.xorshift_len as u8;
+        Self { bytes }
+    }
+
+    /// Fields of the state, or `Error::InvalidState` if it was taken from
+    /// another cipher, round count or counter layout
+    pub(crate) fn snapshot(&self, kind: Kind, rounds: usize, legacy: bool) -> Result<Zeroizing<Snapshot>, Error> {
+        let b = &self.bytes;
+        if b[1] != kind as u8 || u16::from_le_bytes([b[2], b[3]]) as usize != rounds || b[4] != legacy as u8 {
+            return Err(Error::InvalidState);
+        }
+
+        let mut snapshot = Zeroizing::new(Snapshot::default());
+        for (word, chunk) in snapshot.state.iter_mut().zip(b[5..69].chunks_exact(4)) {
+            *word = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+        snapshot.offset    = b[69] as usize;
+        snapshot.exhausted = b[70] == 1;
+        snapshot.prev_dig.copy_from_slice(&b[71..135]);
+        snapshot.partial.copy_from_slice(&b[135..199]);
+        for (word, chunk) in snapshot.xorshift.iter_mut().zip(b[199..231].chunks_exact(4)) {
+            *word = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+        snapshot.xorshift_len = b[231] as usize;
+        Ok(snapshot)
+    }
+
+    /// Serialized state, see the layout above
+    pub fn as_bytes(&self) -> &[u8; Self::LEN] {
+        &self.bytes
+    }
+
+    /// Parses a state written by `as_bytes`.
+    /// Returns `Error::InvalidState` if the length, version or any field is off
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes: [u8; Self::LEN] = bytes.try_into().map_err(|_| Error::InvalidState)?;
+        let state = Self { bytes };
+        let b = &state.bytes;
+        let valid = b[0] == VERSION
+            && (b[1] == Kind::ChaCha as u8 || b[1] == Kind::DChaCha as u8)
+            && b[4] <= 1
+            && b[5..21] == *b"expand 32-byte k"
+            && b[69] < 64
+            && b[70] <= 1
+            && b[231] < 64
+            // a block being used is the one before the counter, which can't be 0
+            && (b[69] == 0 || b[70] == 1 || b[53..57] != [0; 4] || (b[4] == 1 && b[57..61] != [0; 4]));
+        if !valid {
+            return Err(Error::InvalidState);
+        }
+        Ok(state)
+    }
+
+    /// Encrypts and authenticates the state with ChaCha20-Poly1305 under
+    /// `wrapping_key`. `nonce` must never be used twice with the same key
+    pub fn seal(&self, wrapping_key: &[u8; 32], nonce: &[u8; 12]) -> [u8; Self::SEALED_LEN] {
+        let mut sealed = [0u8; Self::SEALED_LEN];
+        let (head, rest) = sealed.split_at_mut(12);
+        let (body, tag) = rest.split_at_mut(Self::LEN);
+        head.copy_from_slice(nonce);
+        body.copy_from_slice(&self.bytes);
+        let t = ChaCha20Poly1305::new(wrapping_key).encrypt(nonce, SEALED_AAD, body);
+        tag.copy_from_slice(&t);
+        sealed
+    }
+
+    /// Decrypts a state sealed by `seal`.
+    /// Returns `Error::TagMismatch` if it was altered or the key is wrong,
+    /// `Error::InvalidState` if it is not a sealed state at all
+    pub fn open(sealed: &[u8], wrapping_key: &[u8; 32]) -> Result<Self, Error> {
+        if sealed.len() != Self::SEALED_LEN {
+            return Err(Error::InvalidState);
+        }
+        let (nonce, rest) = sealed.split_at(12);
+        let (body, tag) = rest.split_at(Self::LEN);
+
+        let mut bytes = Zeroizing::new([0u8; Self::LEN]);
+        bytes.copy_from_slice(body);
+        ChaCha20Poly1305::new(wrapping_key).decrypt(
+            nonce.try_into().unwrap(), SEALED_AAD, bytes.as_mut_slice(), tag.try_into().unwrap()
+        )?;
+        Self::from_bytes(bytes.as_slice())
+    }
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            state: [0; 16],
+            offset: 0,
+            exhausted: false,
+            prev_dig: [0; 64],
+            partial: [0; 64],
+            xorshift: [0; 8],
+            xorshift_len: 0
+        }
+    }
+}
+
+impl Zeroize for Snapshot {
+    fn zeroize(&mut self) {
+        self.state.zeroize();
+        self.offset.zeroize();
+        self.exhausted.zeroize();
+        self.prev_dig.zeroize();
+        self.partial.zeroize();
+        self.xorshift.zeroize();
+        self.xorshift_len.zeroize();
+    }
+}
+
+impl Drop for CipherState {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ChaCha20, DChaCha20, Error};
+    use super::CipherState;
+
+    #[test]
+    fn seal() {
+        let key = [1u8; 32];
+        let wrapping_key = [2u8; 32];
+        let nonce = [3u8; 12];
+
+        let mut cipher = DChaCha20::new(&key, &nonce);
+        cipher.encrypt_stream(&mut [0u8; 100]);
+        let state = cipher.export_state();
+        let sealed = state.seal(&wrapping_key, &[4u8; 12]);
+
+        assert!(!sealed.windows(16).any(|w| w == &key[..16]));
+        assert_eq!(CipherState::open(&sealed, &wrapping_key).unwrap().as_bytes(), state.as_bytes());
+
+        let mut resumed = DChaCha20::from_state(&CipherState::open(&sealed, &wrapping_key).unwrap()).unwrap();
+        let (mut a, mut b) = ([0u8; 100], [0u8; 100]);
+        cipher.encrypt(&mut a);
+        resumed.encrypt(&mut b);
+        assert_eq!(a, b);
+
+        let mut tampered = sealed;
+        tampered[40] ^= 1;
+        assert_eq!(CipherState::open(&tampered, &wrapping_key).err(), Some(Error::TagMismatch));
+        assert_eq!(CipherState::open(&sealed, &[5u8; 32]).err(), Some(Error::TagMismatch));
+        assert_eq!(CipherState::open(&sealed[1..], &wrapping_key).err(), Some(Error::InvalidState));
+    }
+
+    #[test]
+    fn from_bytes() {
+        let state = ChaCha20::new(&[1u8; 32], &[2u8; 12]).export_state();
+        let bytes = *state.as_bytes();
+        assert!(CipherState::from_bytes(&bytes).is_ok());
+        assert_eq!(CipherState::from_bytes(&bytes[1..]).err(), Some(Error::InvalidState));
+
+        for (pos, value) in [(0, 2), (1, 3), (5, 0), (69, 64), (69, 1)] {
+            let mut bad = bytes;
+            bad[pos] = value;
+            assert_eq!(CipherState::from_bytes(&bad).err(), Some(Error::InvalidState), "byte {pos}");
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Helpers shared by the unit tests of several modules.
+
+use core::{mem::{size_of, MaybeUninit}, ptr};
+
+/// Location of a field of type `F`, for `fields_after_drop`
+pub(crate) fn field<F>(ptr: *const F) -> (*const u8, usize) {
+    (ptr.cast(), size_of::<F>())
+}
+
+/// Builds `value` in place, runs `f` on it and drops it. Returns the bytes of
+/// the fields `fields` locates, read just before and just after the drop.
+///
+/// Only the fields are read, never padding, one volatile byte read at a time
+/// so the reads after the drop can't be optimized away. The fields must hold
+/// plain bytes, which their own drop glue may only overwrite
+pub(crate) fn fields_after_drop<T>(
+    value: T, f: impl FnOnce(&mut T), fields: impl Fn(*const T) -> Vec<(*const u8, usize)>
+) -> (Vec<u8>, Vec<u8>) {
+    let read = |ptr: *const T| -> Vec<u8> {
+        fields(ptr).into_iter()
+            .flat_map(|(start, len)| (0..len).map(move |i| {
+                // SAFETY: `start..start + len` is a field of plain data inside the slot
+                unsafe { ptr::read_volatile(start.add(i)) }
+            }))
+            .collect()
+    };
+
+    let mut slot = MaybeUninit::<T>::uninit();
+    let ptr = slot.as_mut_ptr();
+    // SAFETY: `slot` outlives every access and the value is dropped exactly once
+    unsafe {
+        ptr.write(value);
+        f(&mut *ptr);
+        let live = read(ptr);
+        ptr::drop_in_place(ptr);
+        (live, read(ptr))
+    }
+}
+
+pub(crate) fn contains(memory: &[u8], needle: &[u8]) -> bool {
+    memory.windows(needle.len()).any(|w| w == needle)
+}
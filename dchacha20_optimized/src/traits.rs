@@ -0,0 +1,229 @@
+//! RustCrypto `cipher` trait implementations, enabled by the `cipher` feature.
+//!
+//! `ChaCha` gets the full `StreamCipher` and `StreamCipherSeek` set.
+//! `DChaCha` only gets `KeyIvInit`: its keystream depends on the ciphertext,
+//! so encrypting and decrypting are two different operations and it cannot
+//! be used through `apply_keystream`. Seeking is ruled out for the same reason.
+
+use cipher::{
+    consts::{U12, U32, U8},
+    inout::InOutBuf,
+    Iv, IvSizeUser, Key, KeyIvInit, KeySizeUser, OverflowError, SeekNum,
+    StreamCipher, StreamCipherError, StreamCipherSeek
+};
+use zeroize::Zeroizing;
+
+use crate::{chacha20::ChaCha, dchacha20::DChaCha};
+
+/// Keystream generated at once for distinct input and output buffers, 16 blocks
+const SCRATCH_LEN: usize = 64 * 16;
+
+impl<const R: usize, const LEGACY: bool> KeySizeUser for ChaCha<R, LEGACY> {
+    type KeySize = U32;
+}
+
+impl<const R: usize> IvSizeUser for ChaCha<R> {
+    type IvSize = U12;
+}
+
+impl<const R: usize> IvSizeUser for ChaCha<R, true> {
+    type IvSize = U8;
+}
+
+impl<const R: usize> KeyIvInit for ChaCha<R> {
+    fn new(key: &Key<Self>, iv: &Iv<Self>) -> Self {
+        Self::new(key.as_ref(), iv.as_ref())
+    }
+}
+
+impl<const R: usize> KeyIvInit for ChaCha<R, true> {
+    fn new(key: &Key<Self>, iv: &Iv<Self>) -> Self {
+        Self::new(key.as_ref(), iv.as_ref())
+    }
+}
+
+impl<const R: usize, const LEGACY: bool> StreamCipher for ChaCha<R, LEGACY> {
+    fn try_apply_keystream_inout(&mut self, mut buf: InOutBuf<'_, '_, u8>) -> Result<(), StreamCipherError> {
+        if buf.len() as u64 > self.remaining() {
+            return Err(StreamCipherError);
+        }
+
+        // `apply_keystream` hands the same buffer as input and output, which
+        // is encrypted in place
+        let input = buf.get_in().as_ptr();
+        if input == buf.get_out().as_ptr() {
+            self.encrypt(buf.into_out());
+            return Ok(());
+        }
+
+        // distinct buffers go through a scratch buffer, large enough for the
+        // backends working on several blocks at once
+        let mut keystream = Zeroizing::new([0u8; SCRATCH_LEN]);
+        while !buf.is_empty() {
+            let n = buf.len().min(SCRATCH_LEN);
+            let (mut head, rest) = buf.split_at(n);
+            keystream[..n].fill(0);
+            self.encrypt(&mut keystream[..n]);
+            head.xor_in2out(&keystream[..n]);
+            buf = rest;
+        }
+        Ok(())
+    }
+}
+
+impl<const R: usize, const LEGACY: bool> StreamCipherSeek for ChaCha<R, LEGACY> {
+    fn try_current_pos<T: SeekNum>(&self) -> Result<T, OverflowError> {
+        let position = self.position();
+        T::from_block_byte(position.div_ceil(64), (position % 64) as u8, 64)
+    }
+
+    fn try_seek<T: SeekNum>(&mut self, pos: T) -> Result<(), StreamCipherError> {
+        let (block, byte): (u128, u8) = pos.into_block_byte(64).map_err(|_| StreamCipherError)?;
+        let position = block
+            .checked_mul(64)
+            .map(|p| p + byte as u128)
+            .filter(|p| *p <= Self::BLOCKS * 64)
+            .ok_or(StreamCipherError)?;
+        self.set_position(position);
+        Ok(())
+    }
+}
+
+
+impl<const R: usize, const LEGACY: bool> KeySizeUser for DChaCha<R, LEGACY> {
+    type KeySize = U32;
+}
+
+impl<const R: usize> IvSizeUser for DChaCha<R> {
+    type IvSize = U12;
+}
+
+impl<const R: usize> IvSizeUser for DChaCha<R, true> {
+    type IvSize = U8;
+}
+
+impl<const R: usize> KeyIvInit for DChaCha<R> {
+    fn new(key: &Key<Self>, iv: &Iv<Self>) -> Self {
+        Self::new(key.as_ref(), iv.as_ref())
+    }
+}
+
+impl<const R: usize> KeyIvInit for DChaCha<R, true> {
+    fn new(key: &Key<Self>, iv: &Iv<Self>) -> Self {
+        Self::new(key.as_ref(), iv.as_ref())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+    use hex_literal::hex;
+
+    use crate::{ChaCha20, ChaCha20Legacy, DChaCha20};
+
+    fn apply<C: KeyIvInit + StreamCipher>(key: &[u8], iv: &[u8], buffer: &mut [u8]) {
+        let mut cipher = C::new_from_slices(key, iv).unwrap();
+        for chunk in buffer.chunks_mut(37) {
+            cipher.apply_keystream(chunk);
+        }
+    }
+
+    #[test]
+    fn stream_cipher() {
+        // RFC 8439 section 2.4.2, the keystream starts at block 1
+        let key = hex!("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let nonce = hex!("000000000000004a00000000");
+        let msg = "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".as_bytes();
+
+        let mut buffer = [0u8; 64].iter().chain(msg).copied().collect::<Vec<u8>>();
+        apply::<ChaCha20>(&key, &nonce, &mut buffer);
+        let ciphertext = buffer[64..].to_vec();
+        assert_eq!(ciphertext, hex!("6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d"));
+
+        let mut expected = msg.to_vec();
+        ChaCha20Legacy::new(&key, &nonce[4..].try_into().unwrap()).encrypt(&mut expected);
+        let mut buffer = msg.to_vec();
+        apply::<ChaCha20Legacy>(&key, &nonce[4..], &mut buffer);
+        assert_eq!(buffer, expected);
+
+        // Distinct input and output buffers
+        let mut cipher = <ChaCha20 as KeyIvInit>::new(&key.into(), &nonce.into());
+        let mut out = vec![0u8; msg.len()];
+        StreamCipherSeek::seek(&mut cipher, 64u32);
+        cipher.apply_keystream_b2b(msg, &mut out).unwrap();
+        assert_eq!(out, ciphertext);
+    }
+
+    #[test]
+    fn in_place_and_b2b() {
+        let key = [7u8; 32];
+        let nonce = [9u8; 12];
+        let msg = (0..3000).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut expected = msg.clone();
+        ChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        for len in [1, 1023, 1024, 1025, 3000] {
+            let mut buffer = msg[..len].to_vec();
+            <ChaCha20 as KeyIvInit>::new(&key.into(), &nonce.into()).apply_keystream(&mut buffer);
+            assert_eq!(buffer, expected[..len]);
+
+            let mut out = vec![0u8; len];
+            <ChaCha20 as KeyIvInit>::new(&key.into(), &nonce.into())
+                .apply_keystream_b2b(&msg[..len], &mut out).unwrap();
+            assert_eq!(out, expected[..len]);
+        }
+    }
+
+    #[test]
+    fn seek() {
+        let key = [7u8; 32];
+        let nonce = [9u8; 12];
+        let mut keystream = vec![0u8; 300];
+        ChaCha20::new(&key, &nonce).encrypt(&mut keystream);
+
+        let mut cipher = <ChaCha20 as KeyIvInit>::new(&key.into(), &nonce.into());
+        for pos in [0u64, 1, 63, 64, 65, 130, 299] {
+            StreamCipherSeek::seek(&mut cipher, pos);
+            assert_eq!(StreamCipherSeek::current_pos::<u64>(&cipher), pos);
+            let mut buffer = vec![0u8; 300 - pos as usize];
+            cipher.apply_keystream(&mut buffer);
+            assert_eq!(buffer, keystream[pos as usize..]);
+            assert_eq!(StreamCipherSeek::current_pos::<u64>(&cipher), 300);
+        }
+
+        // End of the keystream
+        StreamCipherSeek::seek(&mut cipher, 64u64 << 32);
+        assert_eq!(StreamCipherSeek::current_pos::<u64>(&cipher), 64 << 32);
+        assert!(cipher.try_apply_keystream(&mut [0u8]).is_err());
+        assert!(cipher.try_seek((64u64 << 32) + 1).is_err());
+
+        StreamCipherSeek::seek(&mut cipher, (64u64 << 32) - 10);
+        let mut buffer = [0u8; 11];
+        assert!(cipher.try_apply_keystream(&mut buffer).is_err());
+        assert_eq!(buffer, [0u8; 11]);
+        cipher.apply_keystream(&mut buffer[..10]);
+        assert_eq!(StreamCipherSeek::current_pos::<u64>(&cipher), 64 << 32);
+
+        // The legacy counter goes past u64 bytes
+        let mut cipher = <ChaCha20Legacy as KeyIvInit>::new(&key.into(), &[9u8; 8].into());
+        StreamCipherSeek::seek(&mut cipher, 1u128 << 70);
+        assert_eq!(StreamCipherSeek::current_pos::<u128>(&cipher), 1 << 70);
+        assert!(cipher.try_current_pos::<u64>().is_err());
+        StreamCipherSeek::seek(&mut cipher, (1u128 << 70) - 1);
+        cipher.apply_keystream(&mut [0u8]);
+        assert!(cipher.try_apply_keystream(&mut [0u8]).is_err());
+    }
+
+    #[test]
+    fn dchacha20_key_iv_init() {
+        let key = [7u8; 32];
+        let nonce = [9u8; 12];
+        let mut expected = b"hello".repeat(30);
+        DChaCha20::new(&key, &nonce).encrypt(&mut expected);
+
+        let mut buffer = b"hello".repeat(30);
+        <DChaCha20 as KeyIvInit>::new_from_slices(&key, &nonce).unwrap().encrypt(&mut buffer);
+        assert_eq!(buffer, expected);
+    }
+}